@@ -0,0 +1,92 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use serenity::client::Context;
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use tracing::{error, warn};
+
+use crate::bot_utils::{bot_paths, check_msg, unix_time};
+use crate::storage::StorageError;
+
+/// Serializes appends so concurrent entries don't interleave.
+static AUDIT_LOCK: Mutex<()> = Mutex::new(());
+
+/// A change of a guild setting, stored as one JSON object per line of the audit log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub time: u64,
+    pub guild: GuildId,
+    pub user: UserId,
+    pub setting: String,
+    pub old: String,
+    pub new: String,
+}
+
+impl AuditEntry {
+    pub fn new(guild: GuildId, user: UserId, setting: impl Into<String>, old: impl Into<String>, new: impl Into<String>) -> Self {
+        AuditEntry {
+            time: unix_time(),
+            guild,
+            user,
+            setting: setting.into(),
+            old: old.into(),
+            new: new.into(),
+        }
+    }
+
+    /// Formats the entry for Discord, mentions should be suppressed when sending it.
+    pub fn describe(&self) -> String {
+        format!("<t:{}:f> <@{}> changed `{}`: `{}` -> `{}`", self.time, self.user, self.setting, self.old, self.new)
+    }
+}
+
+fn audit_log_path() -> PathBuf {
+    bot_paths().data_dir.join("audit_log.jsonl")
+}
+
+/// Appends an entry to the audit log next to the config.
+pub fn append_audit_entry(entry: &AuditEntry) -> Result<(), StorageError> {
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+
+    let _guard = AUDIT_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut file = OpenOptions::new().create(true).append(true).open(audit_log_path())?;
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Reads the entries of a guild, newest first. Lines that can't be parsed are skipped.
+pub fn read_audit_entries(guild: GuildId) -> Result<Vec<AuditEntry>, StorageError> {
+    let file = match File::open(audit_log_path()) {
+        Ok(file) => file,
+        Err(why) if why.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(why) => return Err(why.into()),
+    };
+
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        match serde_json::from_str::<AuditEntry>(&line?) {
+            Ok(entry) if entry.guild == guild => entries.push(entry),
+            Ok(_) => {},
+            Err(why) => warn!("Skipping invalid audit log entry: {:?}", why),
+        }
+    }
+    entries.reverse();
+    Ok(entries)
+}
+
+/// Records an entry in the audit log and mirrors it to the log channel of the guild if one is set.
+pub async fn audit(ctx: &Context, log_channel: Option<ChannelId>, entry: AuditEntry) {
+    if let Err(why) = append_audit_entry(&entry) {
+        error!("Audit log could not be written: {:?}", why);
+    }
+    if let Some(channel) = log_channel {
+        check_msg(channel.send_message(&ctx.http, |m| {
+            m.content(entry.describe());
+            m.allowed_mentions(|am| am.empty_parse())
+        }).await);
+    }
+}
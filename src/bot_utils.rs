@@ -1,18 +1,59 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error;
-use std::io::Write;
-use std::sync::{Arc};
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use serde_yaml;
 use serde::{Deserialize, Serialize};
 use serenity::client::Context;
 use serenity::framework::standard::macros::check;
-use serenity::framework::standard::Reason;
+use serenity::framework::standard::{Args, Check, CommandGroup, CommandOptions, Reason};
+use serenity::model::application::{CurrentApplicationInfo, MembershipState};
 use serenity::model::channel::Message;
-use serenity::model::id::{GuildId, UserId};
-use serenity::prelude::{TypeMapKey};
+use serenity::model::guild::Guild;
+use serenity::model::permissions::Permissions;
+use serenity::model::id::{ChannelId, GuildId, RoleId, UserId};
+use serenity::prelude::{TypeMap, TypeMapKey};
 use tokio::sync::{RwLock};
+use tracing::{error, warn};
 
+use crate::commands::audio::Player;
 use crate::entity_id::{EntityId};
+use crate::storage::{storage, StorageError};
+
+const PLACEHOLDER_TOKEN: &str = "<fancy_token>";
+
+/// Locations of the files the bot reads and writes.
+#[derive(Debug)]
+pub struct BotPaths {
+    pub config: PathBuf,
+    pub credentials: PathBuf,
+    pub data_dir: PathBuf,
+}
+
+impl Default for BotPaths {
+    fn default() -> Self {
+        BotPaths {
+            config: PathBuf::from("bot_config.yml"),
+            credentials: PathBuf::from("bot_credentials.yml"),
+            data_dir: PathBuf::from("."),
+        }
+    }
+}
+
+static BOT_PATHS: OnceLock<BotPaths> = OnceLock::new();
+
+/// Sets the file locations, has to be called before any file is read.
+pub fn init_paths(paths: BotPaths){
+    BOT_PATHS.set(paths).expect("Bot paths already initialised!");
+}
+
+pub fn bot_paths() -> &'static BotPaths {
+    BOT_PATHS.get_or_init(BotPaths::default)
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Credentials {
@@ -22,12 +63,21 @@ pub struct Credentials {
 impl Default for Credentials{
     fn default() -> Self {
         Credentials{
-            token:"<fancy_token>".to_string(),
+            token:PLACEHOLDER_TOKEN.to_string(),
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Credentials{
+    pub fn validate(&self) -> Result<(), String>{
+        if self.token.trim().is_empty() || self.token == PLACEHOLDER_TOKEN {
+            return Err(format!("No token configured, set DISCORD_TOKEN or edit {:?}!", bot_paths().credentials));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum BotModes{
     Latex,
     Music,
@@ -35,6 +85,43 @@ pub enum BotModes{
     All,
 }
 
+impl BotModes {
+    fn enables(&self, module: BotModes) -> bool{
+        *self == BotModes::All || *self == module
+    }
+}
+
+impl FromStr for BotModes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "latex" => Ok(BotModes::Latex),
+            "music" => Ok(BotModes::Music),
+            "soundboard" => Ok(BotModes::Soundboard),
+            "all" => Ok(BotModes::All),
+            _ => Err(format!("Unknown module `{}`!", s)),
+        }
+    }
+}
+
+/// Where the slash commands of the bot are registered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SlashCommands{
+    Disabled,
+    /// Registered in every guild when it becomes available, updates apply immediately.
+    Guild,
+    /// Registered once for all guilds, Discord may take up to an hour to apply updates.
+    Global,
+}
+
+impl Default for SlashCommands {
+    /// Off so upgraded configs keep working as before until slash commands are opted into.
+    fn default() -> Self {
+        SlashCommands::Disabled
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum BotPermission {
     Owner,
@@ -42,6 +129,8 @@ pub enum BotPermission {
     Moderator,
     User,
     None,
+    /// Shut out of all commands, overrides `user_default` and every grant.
+    Blocked,
 }
 
 #[allow(unreachable_patterns)]
@@ -49,17 +138,105 @@ impl BotPermission {
     fn level(&self) -> u8{
         match self {
             BotPermission::Owner => u8::MAX,
-            BotPermission::Admin => 3,
-            BotPermission::Moderator => 2,
-            BotPermission::User => 1,
-            BotPermission::None => 0,
+            BotPermission::Admin => 4,
+            BotPermission::Moderator => 3,
+            BotPermission::User => 2,
+            BotPermission::None => 1,
+            BotPermission::Blocked => 0,
             _ => 0
         }
     }
 
-    fn dominates(&self, perm: &BotPermission) -> bool{
+    pub fn dominates(&self, perm: &BotPermission) -> bool{
         self.level() >= perm.level()
     }
+
+    /// Checks that a caller with this permission may change an entity from `current` to `new`,
+    /// the entity has to rank below the caller and `new` must not rank above it.
+    pub fn may_change(&self, current: &BotPermission, new: &BotPermission) -> Result<(), String>{
+        if current.dominates(self) {
+            return Err(format!("Cannot change an entity that is {:?} while being {:?}!", current, self));
+        }
+        if !self.dominates(new) {
+            return Err(format!("Cannot grant {:?} while being {:?}!", new, self));
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for BotPermission {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "owner" => Ok(BotPermission::Owner),
+            "admin" => Ok(BotPermission::Admin),
+            "moderator" => Ok(BotPermission::Moderator),
+            "user" => Ok(BotPermission::User),
+            "none" => Ok(BotPermission::None),
+            _ => Err(format!("Unknown permission `{}`, available are owner/admin/moderator/user/none!", s)),
+        }
+    }
+}
+
+/// Discord permissions that can be mapped onto a `BotPermission`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum NativePermission {
+    Administrator,
+    ManageGuild,
+    ManageMessages,
+}
+
+impl NativePermission {
+    fn permissions(&self) -> Permissions {
+        match self {
+            NativePermission::Administrator => Permissions::ADMINISTRATOR,
+            NativePermission::ManageGuild => Permissions::MANAGE_GUILD,
+            NativePermission::ManageMessages => Permissions::MANAGE_MESSAGES,
+        }
+    }
+
+    /// The mapping enabled with `native_permissions on`.
+    fn default_mapping() -> HashMap<NativePermission, BotPermission> {
+        HashMap::from([
+            (NativePermission::Administrator, BotPermission::Admin),
+            (NativePermission::ManageGuild, BotPermission::Admin),
+            (NativePermission::ManageMessages, BotPermission::Moderator),
+        ])
+    }
+
+    /// Parses the value of the `native_permissions` setting into a mapping.
+    fn parse_mapping(value: &str) -> Result<HashMap<NativePermission, BotPermission>, String> {
+        match value {
+            "on" => Ok(NativePermission::default_mapping()),
+            "off" => Ok(HashMap::default()),
+            _ => value.split(|c: char| c == ',' || c.is_whitespace())
+                      .filter(|entry| !entry.is_empty())
+                      .map(|entry| {
+                          let (native, perm) = entry.split_once('=')
+                                                    .ok_or_else(|| format!("`{}` is not of the form permission=level!", entry))?;
+                          let (native, perm) = (native.parse::<NativePermission>()?, perm.parse::<BotPermission>()?);
+                          if !matches!(perm, BotPermission::Admin | BotPermission::Moderator | BotPermission::User) {
+                              return Err(format!("{:?} cannot be mapped to {:?}!", native, perm));
+                          }
+                          Ok((native, perm))
+                      })
+                      .collect(),
+        }
+    }
+}
+
+impl FromStr for NativePermission {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "administrator" => Ok(NativePermission::Administrator),
+            "manage_guild" => Ok(NativePermission::ManageGuild),
+            "manage_messages" => Ok(NativePermission::ManageMessages),
+            _ => Err(format!("Unknown Discord permission `{}`, available are administrator/manage_guild/manage_messages!", s)),
+        }
+    }
 }
 
 pub struct BotConfig;
@@ -69,32 +246,416 @@ impl TypeMapKey for BotConfig {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ConfigStruct{
+    #[serde(default)]
+    version: u32,
     owner_id: UserId,
-    prefix: char,
+    /// Owners in addition to `owner_id` and the owners of the application.
+    #[serde(default)]
+    owners: Vec<UserId>,
+    /// Treat the members of the team owning the application as owners.
+    #[serde(default = "default_team_owners")]
+    team_owners: bool,
+    /// Only treat team members with one of these team permissions as owners, all if empty.
+    #[serde(default)]
+    team_owner_permissions: Vec<String>,
+    /// Owners of the application, fetched on startup.
+    #[serde(skip)]
+    app_owners: HashSet<UserId>,
+    prefix: String,
     auto_reconnect: bool,
     bot_mode: BotModes,
     activity: serenity::model::gateway::ActivityType,
+    #[serde(default)]
+    activity_texts: Vec<String>,
+    #[serde(default = "default_activity_interval")]
+    activity_interval: u64,
+    #[serde(default)]
+    slash_commands: SlashCommands,
+    /// Directory the soundboard plays from, sounds are given relative to it.
+    #[serde(default = "default_sound_dir")]
+    sound_dir: PathBuf,
+    /// Address to serve Prometheus metrics on, e.g. `127.0.0.1:9100`, disabled if unset.
+    #[serde(default)]
+    metrics_address: Option<SocketAddr>,
+    /// Seconds a shutdown may take before the bot exits without finishing it.
+    #[serde(default = "default_shutdown_timeout")]
+    shutdown_timeout: u64,
     server_cfgs: HashMap<GuildId, ServerAudioStruct>,
+    /// Drop the config of a guild the bot leaves instead of archiving it.
+    #[serde(default)]
+    prune_left_guilds: bool,
+    /// Configs of guilds the bot has left, restored if it joins again.
+    #[serde(default)]
+    archived_server_cfgs: HashMap<GuildId, ServerAudioStruct>,
+}
+
+fn default_sound_dir() -> PathBuf {
+    PathBuf::from("sounds")
 }
+
+fn default_activity_interval() -> u64 {
+    60
+}
+
+pub fn default_shutdown_timeout() -> u64 {
+    10
+}
+
+fn default_team_owners() -> bool {
+    true
+}
+
 impl Default for ConfigStruct{
     fn default() -> Self {
         ConfigStruct{
+            version: CONFIG_VERSION,
             owner_id: UserId(0),
-            prefix: '!',
+            owners: Vec::default(),
+            team_owners: default_team_owners(),
+            team_owner_permissions: Vec::default(),
+            app_owners: HashSet::default(),
+            prefix: "!".to_string(),
             auto_reconnect: false,
             bot_mode: BotModes::Latex,
             activity: serenity::model::gateway::ActivityType::Watching,
+            activity_texts: vec!["{guilds} servers".to_string()],
+            activity_interval: default_activity_interval(),
+            slash_commands: SlashCommands::default(),
+            sound_dir: default_sound_dir(),
+            metrics_address: None,
+            shutdown_timeout: default_shutdown_timeout(),
             server_cfgs: HashMap::default(),
+            prune_left_guilds: false,
+            archived_server_cfgs: HashMap::default(),
         }
     }
 }
 impl ConfigStruct{
-    pub fn init_server(&mut self, guild: GuildId){
-        if !self.server_cfgs.contains_key(&guild){
-            self.server_cfgs.insert(guild, ServerAudioStruct::default());
+    /// Checks the values serde cannot check on its own.
+    pub fn validate(&self) -> Result<(), String>{
+        if !valid_prefix(&self.prefix) {
+            return Err(format!("Invalid prefix `{}`!", self.prefix));
+        }
+        for (guild, server) in &self.server_cfgs {
+            server.validate().map_err(|why| format!("Guild {}: {}", guild, why))?;
+        }
+        Ok(())
+    }
+
+    /// Sets the owners of the application from its info. The team owner is always included,
+    /// team members only if `team_owners` is set and they hold one of `team_owner_permissions`.
+    pub fn set_app_owners(&mut self, info: &CurrentApplicationInfo){
+        self.app_owners.clear();
+        match &info.team {
+            Some(team) => {
+                self.app_owners.insert(team.owner_user_id);
+                if self.team_owners {
+                    let members = team.members.iter()
+                                      .filter(|member| member.membership_state == MembershipState::Accepted)
+                                      .filter(|member| self.team_owner_permissions.is_empty()
+                                                       || member.permissions.iter().any(|perm| self.team_owner_permissions.contains(perm)))
+                                      .map(|member| member.user.id)
+                                      .collect::<Vec<UserId>>();
+                    self.app_owners.extend(members);
+                }
+            },
+            None => {
+                self.app_owners.insert(info.owner.id);
+            },
+        }
+    }
+
+    /// Returns every owner, i.e. `owner_id`, the configured `owners` and the application owners.
+    pub fn owners(&self) -> HashSet<UserId>{
+        std::iter::once(self.owner_id)
+            .chain(self.owners.iter().copied())
+            .chain(self.app_owners.iter().copied())
+            .filter(|owner| owner.0 != 0)
+            .collect()
+    }
+
+    pub fn is_owner(&self, user: UserId) -> bool{
+        user.0 != 0 && (user == self.owner_id || self.owners.contains(&user) || self.app_owners.contains(&user))
+    }
+
+    /// Creates the config of a guild, restoring an archived one if present.
+    /// Returns false if the guild already had a config.
+    pub fn init_server(&mut self, guild: GuildId) -> bool{
+        if self.server_cfgs.contains_key(&guild){
+            return false;
+        }
+        let server = self.archived_server_cfgs.remove(&guild).unwrap_or_default();
+        self.server_cfgs.insert(guild, server);
+        true
+    }
+
+    /// Sets one of the `SERVER_SETTINGS` of a guild from its textual representation.
+    pub fn set_guild_setting(&mut self, guild: GuildId, key: &str, value: &str) -> Result<(), String>{
+        match self.server_cfgs.get_mut(&guild) {
+            Some(server) => server.set_setting(key, value),
+            None => Err("Server config doesnt exist!".to_string()),
+        }
+    }
+
+    /// Returns the textual representation of one of the `SERVER_SETTINGS` of a guild.
+    pub fn get_guild_setting(&self, guild: GuildId, key: &str) -> Option<String>{
+        self.server_cfgs.get(&guild).and_then(|server| server.get_setting(key))
+    }
+
+    /// Resets a guild to the defaults, keeping the grants `caller` may not change.
+    pub fn reset_guild(&mut self, guild: GuildId, caller: BotPermission){
+        if let Some(current) = self.server_cfgs.get_mut(&guild) {
+            *current = current.reset(caller);
+        }
+    }
+
+    pub fn replace_guild(&mut self, guild: GuildId, server: ServerAudioStruct){
+        if let Some(current) = self.server_cfgs.get_mut(&guild) {
+            *current = server;
+        }
+    }
+
+    /// Returns the config of a guild and whether it is archived.
+    pub fn server_config(&self, guild: GuildId) -> Option<(&ServerAudioStruct, bool)>{
+        self.server_cfgs.get(&guild).map(|server| (server, false))
+            .or_else(|| self.archived_server_cfgs.get(&guild).map(|server| (server, true)))
+    }
+
+    /// Iterates over all guild configs and whether they are archived.
+    pub fn server_configs(&self) -> impl Iterator<Item = (GuildId, &ServerAudioStruct, bool)>{
+        self.server_cfgs.iter().map(|(guild, server)| (*guild, server, false))
+            .chain(self.archived_server_cfgs.iter().map(|(guild, server)| (*guild, server, true)))
+    }
+
+    /// Archives or prunes the config of a guild the bot has left.
+    /// Returns false if the guild had no config.
+    pub fn remove_server(&mut self, guild: GuildId) -> bool{
+        match self.server_cfgs.remove(&guild) {
+            Some(server) => {
+                if !self.prune_left_guilds {
+                    self.archived_server_cfgs.insert(guild, server);
+                }
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Lists archived guild configs and configs of guilds not in `current_guilds`.
+    pub fn orphaned_servers(&self, current_guilds: &[GuildId]) -> Vec<GuildId>{
+        let mut orphans: Vec<GuildId> = self.server_cfgs.keys()
+                                            .filter(|guild| !current_guilds.contains(guild))
+                                            .chain(self.archived_server_cfgs.keys())
+                                            .copied()
+                                            .collect();
+        orphans.sort();
+        orphans.dedup();
+        orphans
+    }
+
+    /// Removes the config of a guild, archived or not.
+    pub fn prune_server(&mut self, guild: GuildId){
+        self.server_cfgs.remove(&guild);
+        self.archived_server_cfgs.remove(&guild);
+    }
+
+    pub fn get_activity(&self) -> serenity::model::gateway::ActivityType {
+        self.activity
+    }
+
+    /// Returns the rotating status texts, falling back to a help hint if none are configured.
+    pub fn get_activity_texts(&self) -> Vec<String> {
+        if self.activity_texts.is_empty() {
+            vec![format!("{}help", self.prefix)]
+        } else {
+            self.activity_texts.clone()
         }
     }
 
+    pub fn get_activity_interval(&self) -> u64 {
+        self.activity_interval.max(15)
+    }
+
+    pub fn get_slash_commands(&self) -> SlashCommands {
+        self.slash_commands
+    }
+
+    pub fn get_sound_dir(&self) -> &Path {
+        &self.sound_dir
+    }
+
+    /// Resolves a sound relative to the sound directory, absolute paths and `..` are rejected.
+    pub fn sound_path(&self, path: &str) -> Result<PathBuf, String> {
+        if !inside_dir(Path::new(path)) {
+            return Err(format!("`{}` is not a path inside the sound directory!", path));
+        }
+        Ok(self.sound_dir.join(path))
+    }
+
+    pub fn get_metrics_address(&self) -> Option<SocketAddr> {
+        self.metrics_address
+    }
+
+    pub fn get_shutdown_timeout(&self) -> u64 {
+        self.shutdown_timeout
+    }
+
+    pub fn set_activity(&mut self, activity: serenity::model::gateway::ActivityType, texts: Vec<String>){
+        self.activity = activity;
+        self.activity_texts = texts;
+    }
+
+    pub fn get_guild_prefix(&self, guild: Option<GuildId>) -> String {
+        guild.and_then(|guild| self.server_cfgs.get(&guild))
+             .and_then(|server| server.prefix.clone())
+             .unwrap_or_else(|| self.prefix.clone())
+    }
+
+    pub fn set_guild_prefix(&mut self, guild: GuildId, prefix: Option<String>){
+        if let Some(server) = self.server_cfgs.get_mut(&guild) {
+            server.prefix = prefix;
+        }
+    }
+
+    /// Checks if the given module is enabled for the guild, falling back to the global `bot_mode`
+    /// when the guild has no module set of its own.
+    pub fn guild_module_enabled(&self, guild: Option<GuildId>, module: BotModes) -> bool {
+        match guild.and_then(|guild| self.server_cfgs.get(&guild))
+                   .and_then(|server| server.bot_modes.as_ref()) {
+            Some(modes) => modes.iter().any(|mode| mode.enables(module)),
+            None => self.bot_mode.enables(module),
+        }
+    }
+
+    pub fn get_guild_modes(&self, guild: GuildId) -> Vec<BotModes> {
+        match self.server_cfgs.get(&guild).and_then(|server| server.bot_modes.as_ref()) {
+            Some(modes) => modes.iter().copied().collect(),
+            None => vec![self.bot_mode],
+        }
+    }
+
+    pub fn set_guild_modes(&mut self, guild: GuildId, modes: Option<HashSet<BotModes>>){
+        if let Some(server) = self.server_cfgs.get_mut(&guild) {
+            server.bot_modes = modes;
+        }
+    }
+
+    /// Returns the permission a guild requires for a command, an override of the command itself
+    /// takes precedence over one of its group.
+    pub fn get_command_permission(&self, guild: Option<GuildId>, command: &str, group: Option<&str>) -> Option<BotPermission> {
+        let server = guild.and_then(|guild| self.server_cfgs.get(&guild))?;
+        server.command_permission.get(command)
+              .or_else(|| group.and_then(|group| server.command_permission.get(&group.to_lowercase())))
+              .copied()
+    }
+
+    /// Checks a user with `permission` against the guild override of a command, or its group,
+    /// falling back to `default_permission`, the permission the command was declared with.
+    /// Commands declared without one require nothing unless overridden. Except for the owner,
+    /// the channel restrictions of the command have to allow the channels it is used in as well.
+    pub fn command_access(&self, guild: Option<GuildId>, command: &str, group: Option<&str>, default_permission: Option<BotPermission>,
+                          permission: BotPermission, channels: &[ChannelId]) -> Result<(), String> {
+        if let Some(required) = self.get_command_permission(guild, command, group).or(default_permission) {
+            if !permission.dominates(&required) {
+                return Err("Insufficient Permission!".to_string());
+            }
+        }
+        if permission != BotPermission::Owner && !self.command_channel_allowed(guild, command, group, channels) {
+            return Err(format!("`{}` is not available in this channel!", command));
+        }
+        Ok(())
+    }
+
+    /// Sets the permission override of a command or group, `None` restores the compiled-in default.
+    pub fn set_command_permission(&mut self, guild: GuildId, name: &str, perm: Option<BotPermission>){
+        if let Some(server) = self.server_cfgs.get_mut(&guild) {
+            match perm {
+                Some(perm) => server.command_permission.insert(name.to_lowercase(), perm),
+                None => server.command_permission.remove(&name.to_lowercase()),
+            };
+        }
+    }
+
+    /// Checks the channel restrictions of a command, then of its group, against the channels it
+    /// is used in. Without a restriction that decides, the command is allowed.
+    pub fn command_channel_allowed(&self, guild: Option<GuildId>, command: &str, group: Option<&str>, channels: &[ChannelId]) -> bool {
+        let server = match guild.and_then(|guild| self.server_cfgs.get(&guild)) {
+            Some(server) => server,
+            None => return true,
+        };
+        let group = group.map(str::to_lowercase);
+        [Some(command), group.as_deref()].iter()
+            .flatten()
+            .filter_map(|name| server.command_channels.get(*name))
+            .find_map(|restriction| restriction.verdict(channels))
+            .unwrap_or(true)
+    }
+
+    /// Adds a channel to the allow or deny list of a command or group.
+    pub fn restrict_command_channel(&mut self, guild: GuildId, name: &str, channel: ChannelId, allow: bool){
+        if let Some(server) = self.server_cfgs.get_mut(&guild) {
+            let restriction = server.command_channels.entry(name.to_lowercase()).or_default();
+            if allow {
+                restriction.deny.remove(&channel);
+                restriction.allow.insert(channel);
+            } else {
+                restriction.allow.remove(&channel);
+                restriction.deny.insert(channel);
+            }
+        }
+    }
+
+    /// Removes all channel restrictions of a command or group.
+    pub fn clear_command_channels(&mut self, guild: GuildId, name: &str){
+        if let Some(server) = self.server_cfgs.get_mut(&guild) {
+            server.command_channels.remove(&name.to_lowercase());
+        }
+    }
+
+    /// Types the legacy entities of a guild config by looking their ids up in the guild.
+    /// Returns false if there was nothing to resolve.
+    pub fn resolve_legacy_entities(&mut self, guild: &Guild) -> bool {
+        match self.server_cfgs.get_mut(&guild.id) {
+            Some(server) => server.resolve_legacy_entities(guild),
+            None => false,
+        }
+    }
+
+    /// Grants a permission until the unix time `expires`, see `expire_grants`.
+    pub fn grant_temporary(&mut self, guild: GuildId, entity: EntityId, perm: BotPermission, expires: u64){
+        if let Some(server) = self.server_cfgs.get_mut(&guild) {
+            server.grant_temporary(entity, perm, expires);
+        }
+    }
+
+    /// Revokes the temporary grants that expired by the unix time `now`.
+    pub fn expire_grants(&mut self, now: u64) -> Vec<ExpiredGrant>{
+        let mut expired = Vec::new();
+        for (guild, server) in self.server_cfgs.iter_mut() {
+            for (entity, revoked, restored) in server.expire_grants(now) {
+                expired.push(ExpiredGrant {
+                    guild: *guild,
+                    entity,
+                    revoked,
+                    restored,
+                });
+            }
+        }
+        expired
+    }
+
+    pub fn get_guild_log_channel(&self, guild: GuildId) -> Option<ChannelId> {
+        self.server_cfgs.get(&guild).and_then(|server| server.log_channel)
+    }
+
+    /// Returns the permission assigned to an entity itself, without roles or defaults.
+    pub fn get_entity_permission(&self, guild: GuildId, entity: EntityId) -> BotPermission {
+        self.server_cfgs.get(&guild)
+            .and_then(|server| server.entity_permission.get(&entity))
+            .copied()
+            .unwrap_or(BotPermission::None)
+    }
+
     pub fn insert_entity_guild(&mut self, guild: GuildId, entity: impl Into<EntityId>, perm: BotPermission){
         if let Some(server) = self.server_cfgs.get_mut(&guild) {
             server.insert_entity_permission(entity, perm);
@@ -144,12 +705,75 @@ impl ConfigStruct{
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Channels a command or group may or may not be used in.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChannelRestriction {
+    #[serde(default)]
+    pub allow: HashSet<ChannelId>,
+    #[serde(default)]
+    pub deny: HashSet<ChannelId>,
+}
+
+impl ChannelRestriction {
+    /// A denied channel forbids the command, an allowed channel permits it and a non-empty allow
+    /// list forbids every other channel. `None` if the restriction has no say.
+    fn verdict(&self, channels: &[ChannelId]) -> Option<bool> {
+        if channels.iter().any(|channel| self.deny.contains(channel)) {
+            Some(false)
+        } else if channels.iter().any(|channel| self.allow.contains(channel)) {
+            Some(true)
+        } else if !self.allow.is_empty() {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+/// A permission granted until the unix time `expires`, after which `previous` is restored.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TemporaryGrant {
+    pub expires: u64,
+    pub previous: Option<BotPermission>,
+}
+
+/// A temporary grant revoked by `ConfigStruct::expire_grants`.
+#[derive(Debug, Clone, Copy)]
+pub struct ExpiredGrant {
+    pub guild: GuildId,
+    pub entity: EntityId,
+    pub revoked: Option<BotPermission>,
+    pub restored: Option<BotPermission>,
+}
+
+/// Settings that can be changed with `ServerAudioStruct::set_setting`.
+pub const SERVER_SETTINGS: &[&str] = &["volume", "auto_playlist", "user_default", "prefix", "modules", "log_channel", "native_permissions"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerAudioStruct {
     volume: u8,
     auto_playlist: bool,
     user_default: bool,
     entity_permission: HashMap<EntityId, BotPermission>,
+    #[serde(default)]
+    prefix: Option<String>,
+    #[serde(default)]
+    bot_modes: Option<HashSet<BotModes>>,
+    /// Permission overrides keyed by command or group name.
+    #[serde(default)]
+    command_permission: HashMap<String, BotPermission>,
+    /// Channel restrictions keyed by command or group name.
+    #[serde(default)]
+    command_channels: HashMap<String, ChannelRestriction>,
+    /// Expiry of the entries of `entity_permission` that were granted temporarily.
+    #[serde(default)]
+    temporary_grants: HashMap<EntityId, TemporaryGrant>,
+    /// Channel the bot posts notes about permission changes to.
+    #[serde(default)]
+    log_channel: Option<ChannelId>,
+    /// Discord permissions that grant a bot permission to members, empty if disabled.
+    #[serde(default)]
+    native_permissions: HashMap<NativePermission, BotPermission>,
 }
 impl Default for ServerAudioStruct{
     fn default() -> Self {
@@ -158,18 +782,234 @@ impl Default for ServerAudioStruct{
             auto_playlist: false,
             user_default: false,
             entity_permission: HashMap::default(),
+            prefix: None,
+            bot_modes: None,
+            command_permission: HashMap::default(),
+            command_channels: HashMap::default(),
+            temporary_grants: HashMap::default(),
+            log_channel: None,
+            native_permissions: HashMap::default(),
         }
     }
 }
 
 impl ServerAudioStruct{
+    pub fn validate(&self) -> Result<(), String>{
+        if !(10..=100).contains(&self.volume) {
+            return Err(format!("Volume {} is outside of 10..100!", self.volume));
+        }
+        if let Some(prefix) = &self.prefix {
+            if !valid_prefix(prefix) {
+                return Err(format!("Invalid prefix `{}`!", prefix));
+            }
+        }
+        for (native, perm) in &self.native_permissions {
+            if !matches!(perm, BotPermission::Admin | BotPermission::Moderator | BotPermission::User) {
+                return Err(format!("{:?} cannot be mapped to {:?}!", native, perm));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn volume(&self) -> u8{
+        self.volume
+    }
+
+    pub fn auto_playlist(&self) -> bool{
+        self.auto_playlist
+    }
+
+    pub fn user_default(&self) -> bool{
+        self.user_default
+    }
+
+    pub fn prefix(&self) -> Option<&str>{
+        self.prefix.as_deref()
+    }
+
+    pub fn bot_modes(&self) -> Option<&HashSet<BotModes>>{
+        self.bot_modes.as_ref()
+    }
+
+    pub fn entity_permissions(&self) -> &HashMap<EntityId, BotPermission>{
+        &self.entity_permission
+    }
+
+    pub fn command_permissions(&self) -> &HashMap<String, BotPermission>{
+        &self.command_permission
+    }
+
+    pub fn command_channels(&self) -> &HashMap<String, ChannelRestriction>{
+        &self.command_channels
+    }
+
+    pub fn temporary_grants(&self) -> &HashMap<EntityId, TemporaryGrant>{
+        &self.temporary_grants
+    }
+
+    pub fn log_channel(&self) -> Option<ChannelId>{
+        self.log_channel
+    }
+
+    pub fn native_permissions(&self) -> &HashMap<NativePermission, BotPermission>{
+        &self.native_permissions
+    }
+
+    /// Checks that a caller may set `native_permissions` to `value`, like `make_admin` only owners
+    /// may turn the mapping on or map a Discord permission to `Admin`.
+    pub fn may_set_native_permissions(&self, caller: BotPermission, value: &str) -> Result<(), String>{
+        if caller == BotPermission::Owner {
+            return Ok(());
+        }
+        let mapping = NativePermission::parse_mapping(value)?;
+        if self.native_permissions.is_empty() && !mapping.is_empty() {
+            return Err("Only owners can turn native permissions on!".to_string());
+        }
+        if mapping.values().any(|perm| *perm == BotPermission::Admin) {
+            return Err("Only owners can map Discord permissions to Admin!".to_string());
+        }
+        Ok(())
+    }
+
+    /// Returns the textual representation of one of the `SERVER_SETTINGS`.
+    pub fn get_setting(&self, key: &str) -> Option<String>{
+        let field = match key {
+            "modules" => "bot_modes",
+            key if SERVER_SETTINGS.contains(&key) => key,
+            _ => return None,
+        };
+        let value = serde_yaml::to_value(self).ok()?;
+        value.get(field).map(yaml_value_string)
+    }
+
+    /// Sets one of the `SERVER_SETTINGS` from its textual representation.
+    pub fn set_setting(&mut self, key: &str, value: &str) -> Result<(), String>{
+        let parse_bool = |value: &str| value.parse::<bool>().map_err(|_| format!("`{}` is not true/false!", value));
+        match key {
+            "volume" => {
+                let volume = value.parse::<u8>().map_err(|_| format!("`{}` is not a number!", value))?;
+                if !(10..=100).contains(&volume) {
+                    return Err("Volume must be within 10..100!".to_string());
+                }
+                self.volume = volume;
+            },
+            "auto_playlist" => self.auto_playlist = parse_bool(value)?,
+            "user_default" => self.user_default = parse_bool(value)?,
+            "prefix" => {
+                self.prefix = match value {
+                    "default" => None,
+                    _ if valid_prefix(value) => Some(value.to_string()),
+                    _ => return Err("Prefix must be 1 to 8 characters without whitespace!".to_string()),
+                };
+            },
+            "modules" => {
+                self.bot_modes = match value {
+                    "default" => None,
+                    _ => Some(value.split(|c: char| c == ',' || c.is_whitespace())
+                                   .filter(|mode| !mode.is_empty())
+                                   .map(str::parse::<BotModes>)
+                                   .collect::<Result<HashSet<BotModes>, String>>()?),
+                };
+            },
+            "native_permissions" => {
+                self.native_permissions = NativePermission::parse_mapping(value)?;
+            },
+            "log_channel" => {
+                self.log_channel = match value {
+                    "none" => None,
+                    _ => Some(value.parse::<ChannelId>().map_err(|_| format!("`{}` is not a channel!", value))?),
+                };
+            },
+            _ => return Err(format!("Unknown setting `{}`, available are {}!", key, SERVER_SETTINGS.join(", "))),
+        }
+        Ok(())
+    }
+
+    /// Returns the default config with the grants of this one that `caller` may not change.
+    pub fn reset(&self, caller: BotPermission) -> ServerAudioStruct{
+        let mut server = ServerAudioStruct::default();
+        for (entity, perm) in &self.entity_permission {
+            if caller.may_change(perm, &BotPermission::None).is_err() {
+                server.entity_permission.insert(*entity, *perm);
+                if let Some(grant) = self.temporary_grants.get(entity) {
+                    server.temporary_grants.insert(*entity, *grant);
+                }
+            }
+        }
+        server
+    }
+
+    /// Checks that a caller may replace the permission grants of this config with those of `new`,
+    /// every grant that differs has to pass `BotPermission::may_change`.
+    pub fn may_replace_permissions(&self, caller: BotPermission, new: &ServerAudioStruct) -> Result<(), String>{
+        let entities: HashSet<&EntityId> = self.entity_permission.keys().chain(new.entity_permission.keys()).collect();
+        for entity in entities {
+            let current = self.entity_permission.get(entity).copied().unwrap_or(BotPermission::None);
+            let perm = new.entity_permission.get(entity).copied().unwrap_or(BotPermission::None);
+            if current != perm {
+                caller.may_change(&current, &perm).map_err(|why| format!("`{}`: {}", entity, why))?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn insert_entity_permission(&mut self, entity: impl Into<EntityId>, perm: BotPermission){
+        let entity = entity.into();
+        // a permanent change replaces a temporary grant
+        self.temporary_grants.remove(&entity);
         if perm != BotPermission::None {
-            self.entity_permission.insert(entity.into(), perm);
+            self.entity_permission.insert(entity, perm);
         }else{
-            self.entity_permission.remove(&entity.into());
+            self.entity_permission.remove(&entity);
         }
     }
+
+    /// Types the legacy entities by looking their ids up in the guild.
+    /// Returns false if there was nothing to resolve.
+    pub fn resolve_legacy_entities(&mut self, guild: &Guild) -> bool {
+        let legacy: Vec<u64> = self.entity_permission.keys()
+                                   .filter_map(|entity| match entity {
+                                       EntityId::Legacy(id) => Some(*id),
+                                       _ => None,
+                                   })
+                                   .collect();
+        for id in &legacy {
+            if let Some(perm) = self.entity_permission.remove(&EntityId::Legacy(*id)) {
+                self.entity_permission.insert(EntityId::classify(guild, *id), perm);
+            }
+        }
+        !legacy.is_empty()
+    }
+
+    /// Grants a permission until the unix time `expires`. Repeated grants keep the permission
+    /// from before the first one to restore.
+    pub fn grant_temporary(&mut self, entity: EntityId, perm: BotPermission, expires: u64){
+        let previous = match self.temporary_grants.get(&entity) {
+            Some(grant) => grant.previous,
+            None => self.entity_permission.get(&entity).copied(),
+        };
+        self.temporary_grants.insert(entity, TemporaryGrant { expires, previous });
+        self.entity_permission.insert(entity, perm);
+    }
+
+    /// Restores the previous permission of every grant expired by the unix time `now`.
+    /// Returns the entities with their revoked and restored permission.
+    fn expire_grants(&mut self, now: u64) -> Vec<(EntityId, Option<BotPermission>, Option<BotPermission>)>{
+        let expired: Vec<EntityId> = self.temporary_grants.iter()
+                                         .filter(|(_, grant)| grant.expires <= now)
+                                         .map(|(entity, _)| *entity)
+                                         .collect();
+        expired.into_iter()
+               .filter_map(|entity| {
+                   let grant = self.temporary_grants.remove(&entity)?;
+                   let revoked = match grant.previous {
+                       Some(previous) => self.entity_permission.insert(entity, previous),
+                       None => self.entity_permission.remove(&entity),
+                   };
+                   Some((entity, revoked, grant.previous))
+               })
+               .collect()
+    }
     pub fn set_volume(&mut self, volume: u8){
         self.volume = volume.clamp( 10, 100);
     }
@@ -178,57 +1018,391 @@ impl ServerAudioStruct{
 pub fn write_example_credentials(){
     let f = std::fs::OpenOptions::new()
         .write(true)
-        .create(true)
-        .open("bot_credentials.yml")
+        .create_new(true)
+        .open(&bot_paths().credentials)
         .expect("Couldn't open file.");
     serde_yaml::to_writer(f, &Credentials::default()).unwrap();
-    println!("Failed to read credential file!\nExample file written instead.");
+    warn!("Failed to read credential file!\nExample file written instead.");
+}
+
+pub fn credentials_exist() -> bool {
+    bot_paths().credentials.exists()
 }
 
+/// Reads the credentials, a token in the `DISCORD_TOKEN` environment variable takes precedence over the file.
 pub fn read_credentials() -> Result<Credentials, Box<dyn error::Error>>{
-    let f = std::fs::File::open("./bot_credentials.yml")?;
-    Ok::<Credentials, _>(serde_yaml::from_reader(f)?)
+    let cred = match std::env::var("DISCORD_TOKEN") {
+        Ok(token) if !token.is_empty() => Credentials{ token },
+        _ => {
+            let f = std::fs::File::open(&bot_paths().credentials)?;
+            serde_yaml::from_reader(f)?
+        },
+    };
+    cred.validate()?;
+    Ok(cred)
 }
 
 pub fn write_example_config(){
     let f = std::fs::OpenOptions::new()
         .write(true)
-        .create(true)
-        .open("bot_config.yml")
+        .create_new(true)
+        .open(&bot_paths().config)
         .expect("Couldn't open file.");
     serde_yaml::to_writer(f, &ConfigStruct::default()).unwrap();
-    println!("Failed to read config file!\nExample file written instead.");
+    warn!("Failed to read config file!\nExample file written instead.");
 }
 
-pub fn read_config() -> Result<ConfigStruct, Box<dyn error::Error>>{
-    let f = std::fs::File::open("./bot_config.yml")?;
-    Ok::<ConfigStruct, _>(serde_yaml::from_reader(f)?)
+pub fn config_exists() -> bool {
+    storage().exists()
 }
 
-pub fn write_config(cfg: &ConfigStruct) -> serde_yaml::Result<()>{
-    {
-        let mut f = std::fs::OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .create(true)
-            .open("bot_config.yml")
-            .expect("Couldn't open file.");
-        serde_yaml::to_writer(&f, cfg)?;
-        f.flush().expect("Failed to flush cfg!");
+/// Version 0 configs predate the `version` field, their layout is otherwise compatible.
+fn migrate_v0_v1(_cfg: &mut serde_yaml::Value){}
+
+/// Version 1 stored entities as bare ids. They are kept as legacy entities until their guild
+/// tells whether they are users, roles or channels.
+fn migrate_v1_v2(cfg: &mut serde_yaml::Value){
+    for key in ["server_cfgs", "archived_server_cfgs"] {
+        let servers = match cfg.get_mut(key).and_then(serde_yaml::Value::as_mapping_mut) {
+            Some(servers) => servers,
+            None => continue,
+        };
+        for server in servers.values_mut() {
+            if let Some(entities) = server.get_mut("entity_permission").and_then(serde_yaml::Value::as_mapping_mut) {
+                *entities = std::mem::take(entities).into_iter()
+                                                    .map(|(entity, perm)| match entity.as_u64() {
+                                                        Some(id) => (serde_yaml::Value::from(format!("legacy:{}", id)), perm),
+                                                        None => (entity, perm),
+                                                    })
+                                                    .collect();
+            }
+        }
     }
-    Ok(())
+}
+
+/// Migrations from each config version to the next, indexed by the version they migrate from.
+const CONFIG_MIGRATIONS: &[fn(&mut serde_yaml::Value)] = &[migrate_v0_v1, migrate_v1_v2];
+pub const CONFIG_VERSION: u32 = CONFIG_MIGRATIONS.len() as u32;
+
+/// Migrates a config of any supported version to the current layout.
+pub fn parse_config_value(mut value: serde_yaml::Value) -> Result<ConfigStruct, StorageError>{
+    let version = value.get("version").and_then(serde_yaml::Value::as_u64).unwrap_or(0) as usize;
+    if version > CONFIG_MIGRATIONS.len() {
+        return Err(format!("Config version {} is newer than the supported version {}!", version, CONFIG_VERSION).into());
+    }
+    for migration in &CONFIG_MIGRATIONS[version..] {
+        migration(&mut value);
+    }
+
+    let mut cfg: ConfigStruct = serde_yaml::from_value(value)?;
+    cfg.version = CONFIG_VERSION;
+    Ok(cfg)
+}
+
+pub fn parse_config(reader: impl std::io::Read) -> Result<ConfigStruct, StorageError>{
+    parse_config_value(serde_yaml::from_reader(reader)?)
+}
+
+/// Serializes a guild config together with the config version for `import_server_config`.
+pub fn export_server_config(server: &ServerAudioStruct) -> Result<String, serde_yaml::Error>{
+    let mut export = serde_yaml::Mapping::new();
+    export.insert("version".into(), CONFIG_VERSION.into());
+    export.insert("server".into(), serde_yaml::to_value(server)?);
+    serde_yaml::to_string(&export)
+}
+
+/// Parses a guild config written by `export_server_config`, migrating it if it was
+/// exported by an older version.
+pub fn import_server_config(input: &[u8]) -> Result<ServerAudioStruct, String>{
+    let export: serde_yaml::Value = serde_yaml::from_slice(input).map_err(|why| format!("Invalid YAML: {}", why))?;
+    let version = export.get("version").cloned().ok_or("Missing `version`!")?;
+    let server = export.get("server").cloned().ok_or("Missing `server`!")?;
+
+    // Wrap the guild config into a full config so the config migrations apply to it.
+    let mut servers = serde_yaml::Mapping::new();
+    servers.insert(0u64.into(), server);
+    let mut cfg = serde_yaml::to_value(ConfigStruct::default()).map_err(|why| why.to_string())?;
+    if let serde_yaml::Value::Mapping(map) = &mut cfg {
+        map.insert("version".into(), version);
+        map.insert("server_cfgs".into(), serde_yaml::Value::Mapping(servers));
+    }
+    let mut cfg = parse_config_value(cfg).map_err(|why| format!("Invalid server config: {}", why))?;
+
+    let server = cfg.server_cfgs.remove(&GuildId(0)).ok_or("Missing `server`!")?;
+    server.validate()?;
+    Ok(server)
+}
+
+pub fn read_config() -> Result<ConfigStruct, StorageError>{
+    storage().load()
+}
+
+pub fn write_config(cfg: &ConfigStruct) -> Result<(), StorageError>{
+    storage().save(cfg)
+}
+
+pub fn write_guild_config(cfg: &ConfigStruct, guild: GuildId) -> Result<(), StorageError>{
+    storage().save_guild(cfg, guild)
+}
+
+pub fn write_entity_permission(cfg: &ConfigStruct, guild: GuildId, entity: impl Into<EntityId>) -> Result<(), StorageError>{
+    storage().save_entity_permission(cfg, guild, entity.into())
+}
+
+/// Re-reads the config file, validates it and swaps it into the `BotConfig` entry of `data`.
+/// Volumes of currently playing tracks are updated to the new values.
+/// Returns the list of changed values.
+pub async fn reload_config(data: &RwLock<TypeMap>) -> Result<Vec<String>, String>{
+    let new_cfg = read_config().map_err(|why| format!("Config could not be read: {}", why))?;
+    new_cfg.validate()?;
+
+    let data = data.read().await;
+    let bot_config = match data.get::<BotConfig>() {
+        Some(v) => v,
+        None => return Err("Bot config failed!".to_string()),
+    };
+    let mut bot_config = bot_config.write().await;
+
+    let mut new_cfg = new_cfg;
+    new_cfg.app_owners = bot_config.app_owners.clone();
+    let changes = yaml_diff(&*bot_config, &new_cfg);
+    *bot_config = new_cfg;
+
+    if let Some(players) = data.get::<Player>() {
+        for (guild, track_handler) in players {
+            let volume = bot_config.get_guild_volume(*guild);
+            if let Err(why) = track_handler.set_volume((volume as f32)/100f32) {
+                warn!("Can not set volume in {}: {:?}", guild, why);
+            }
+        }
+    }
+    Ok(changes)
+}
+
+fn yaml_value_string(value: &serde_yaml::Value) -> String{
+    match value {
+        serde_yaml::Value::Null => "none".to_string(),
+        serde_yaml::Value::Bool(b) => b.to_string(),
+        serde_yaml::Value::Number(n) => n.to_string(),
+        serde_yaml::Value::String(s) => s.clone(),
+        _ => serde_yaml::to_string(value).unwrap_or_default().trim().replace('\n', " "),
+    }
+}
+
+fn yaml_value_diff(path: &str, old: &serde_yaml::Value, new: &serde_yaml::Value, changes: &mut Vec<String>){
+    match (old, new) {
+        (serde_yaml::Value::Mapping(old_map), serde_yaml::Value::Mapping(new_map)) => {
+            for (key, old_value) in old_map {
+                let key_path = format!("{}{}.", path, yaml_value_string(key));
+                match new_map.get(key) {
+                    Some(new_value) => yaml_value_diff(&key_path, old_value, new_value, changes),
+                    None => changes.push(format!("{}: {} -> removed", key_path.trim_end_matches('.'), yaml_value_string(old_value))),
+                }
+            }
+            for (key, new_value) in new_map {
+                if !old_map.contains_key(key) {
+                    let key_path = format!("{}{}", path, yaml_value_string(key));
+                    changes.push(format!("{}: added -> {}", key_path, yaml_value_string(new_value)));
+                }
+            }
+        },
+        _ => {
+            if old != new {
+                changes.push(format!("{}: {} -> {}", path.trim_end_matches('.'), yaml_value_string(old), yaml_value_string(new)));
+            }
+        },
+    }
+}
+
+/// Lists the values that differ between the YAML representations of `old` and `new`
+/// as `path.to.value: old -> new`.
+pub fn yaml_diff<T: Serialize>(old: &T, new: &T) -> Vec<String>{
+    let mut changes = Vec::new();
+    match (serde_yaml::to_value(old), serde_yaml::to_value(new)) {
+        (Ok(old), Ok(new)) => yaml_value_diff("", &old, &new, &mut changes),
+        _ => changes.push("Values could not be compared!".to_string()),
+    }
+    changes
+}
+
+/// Reports a failed config write to the channel of the message.
+pub async fn check_write(ctx: &Context, msg: &Message, result: Result<(), StorageError>) -> bool{
+    if let Err(why) = result {
+        error!("Config could not be written: {:?}", why);
+        check_msg(msg.channel_id.say(&ctx.http, "Config could not be written!").await);
+        return false;
+    }
+    true
+}
+
+/// Writes the config and reports a failure to the channel of the message.
+pub async fn save_config(ctx: &Context, msg: &Message, cfg: &ConfigStruct) -> bool{
+    check_write(ctx, msg, write_config(cfg)).await
+}
+
+/// Checks that a relative path can't leave the directory it is joined to.
+pub fn inside_dir(path: &Path) -> bool {
+    path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// Returns the current unix time in seconds.
+pub fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
+
+/// Parses durations like `90s`, `30m`, `3h`, `2d`, `1w` or combinations like `1d12h`.
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid duration `{}`, use e.g. 30m, 3h or 1d12h!", input);
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    for c in input.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        let value = number.parse::<u64>().map_err(|_| invalid())?;
+        number.clear();
+        total = value.checked_mul(unit)
+                     .and_then(|seconds| total.checked_add(seconds))
+                     .ok_or_else(invalid)?;
+    }
+    if !number.is_empty() || total == 0 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(total))
+}
+
+/// Checks that a prefix is usable, i.e. not empty, without whitespace and at most 8 characters long.
+pub fn valid_prefix(prefix: &str) -> bool {
+    !prefix.is_empty() && prefix.chars().count() <= 8 && !prefix.chars().any(char::is_whitespace)
+}
+
+/// Shortens a multi-line text to at most `max_len` bytes by dropping whole lines from the end.
+pub fn truncate_lines(text: &str, max_len: usize) -> String {
+    if text.len() <= max_len {
+        return text.to_string();
+    }
+    let mut end = max_len.saturating_sub(4);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let end = text[..end].rfind('\n').unwrap_or(0);
+    format!("{}\n...", &text[..end])
 }
 
 /// Checks that a message successfully sent; if not, then logs why to stdout.
 pub fn check_msg(result: serenity::Result<Message>) {
     if let Err(why) = result {
-        println!("Error sending message: {:?}", why);
+        error!("Error sending message: {:?}", why);
+    }
+}
+
+/// Where a permission resolved by `explain_permission` comes from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PermissionSource {
+    Owner,
+    UserDefault,
+    Everyone,
+    Native(NativePermission),
+    User(UserId),
+    Role(RoleId),
+    Channel(ChannelId),
+    VoiceChannel(ChannelId),
+}
+
+impl fmt::Display for PermissionSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PermissionSource::Owner => write!(f, "bot owner"),
+            PermissionSource::UserDefault => write!(f, "server user default"),
+            PermissionSource::Everyone => write!(f, "@everyone"),
+            PermissionSource::Native(native) => write!(f, "Discord permission {:?}", native),
+            PermissionSource::User(user) => write!(f, "user <@{}>", user),
+            PermissionSource::Role(role) => write!(f, "role <@&{}>", role),
+            PermissionSource::Channel(channel) => write!(f, "channel <#{}>", channel),
+            PermissionSource::VoiceChannel(channel) => write!(f, "voice channel <#{}>", channel),
+        }
+    }
+}
+
+/// A resolved permission together with every source that was considered and what it grants.
+#[derive(Debug, Clone)]
+pub struct PermissionExplanation {
+    pub permission: BotPermission,
+    pub sources: Vec<(PermissionSource, Option<BotPermission>)>,
+    /// Index into `sources` of the source the permission comes from.
+    pub winner: Option<usize>,
+}
+
+impl PermissionExplanation {
+    fn consider(&mut self, source: PermissionSource, perm: Option<BotPermission>){
+        if let Some(perm) = perm {
+            // a block wins over every grant, before or after it
+            let wins = match (self.permission, perm) {
+                (BotPermission::Blocked, _) => false,
+                (_, BotPermission::Blocked) => true,
+                (current, perm) => !current.dominates(&perm),
+            };
+            if wins {
+                self.permission = perm;
+                self.winner = Some(self.sources.len());
+            }
+        }
+        self.sources.push((source, perm));
+    }
+
+    /// Iterates over the sources that granted a permission.
+    pub fn grants(&self) -> impl Iterator<Item = (PermissionSource, BotPermission)> + '_ {
+        self.sources.iter().filter_map(|(source, perm)| perm.map(|perm| (*source, perm)))
     }
 }
 
-pub async fn user_permission(ctx: &Context, msg: &Message, entity_id: impl Into<EntityId>) -> Result<BotPermission, Reason>{
+/// Returns the voice channel the user is in if the bot is connected to the same one.
+fn shared_voice_channel(ctx: &Context, guild: &Guild, user: UserId) -> Option<ChannelId>{
+    let user_channel = guild.voice_states.get(&user)?.channel_id?;
+    let bot_channel = guild.voice_states.get(&ctx.cache.current_user_id())?.channel_id?;
+    (user_channel == bot_channel).then_some(user_channel)
+}
+
+/// Lists the channels a command is used in, that is the channel it was sent in and the voice
+/// channel the author shares with the bot.
+fn command_channels(ctx: &Context, guild: Option<GuildId>, channel: ChannelId, author: UserId) -> Vec<ChannelId>{
+    let mut channels = vec![channel];
+    if let Some(voice_channel) = guild.and_then(|guild| guild.to_guild_cached(&ctx.cache)).and_then(|guild| shared_voice_channel(ctx, &guild, author)) {
+        channels.push(voice_channel);
+    }
+    channels
+}
+
+/// Resolves the permission of a user in the guild and channel of a message, see
+/// `explain_permission_in`.
+pub async fn explain_permission(ctx: &Context, msg: &Message, user: UserId) -> Result<PermissionExplanation, Reason>{
+    explain_permission_in(ctx, msg.guild_id, msg.channel_id, user).await
+}
+
+/// Resolves the permission of a user in a guild and channel.
+///
+/// The bot owner is always `Owner`. A `Blocked` entry of the user or one of their roles blocks
+/// the user, everyone else gets the highest of
+/// 1. `User` if the guild has `user_default` set,
+/// 2. the `everyone` entry,
+/// 3. the entry of the user,
+/// 4. the entries of the roles of the user,
+/// 5. the Discord permissions of the user mapped by `native_permissions`,
+/// 6. the entry of the channel,
+/// 7. the entry of the voice channel the user shares with the bot.
+///
+/// Channel restrictions of commands are applied on top of this by `check_command_access`.
+pub async fn explain_permission_in(ctx: &Context, guild: Option<GuildId>, channel: ChannelId, user: UserId) -> Result<PermissionExplanation, Reason>{
     let data = ctx.data.read().await;
-    let entity_id = entity_id.into();
     let bot_config = match data.get::<BotConfig>() {
         Some(v) => v,
         None => {
@@ -237,71 +1411,538 @@ pub async fn user_permission(ctx: &Context, msg: &Message, entity_id: impl Into<
     };
     let bot_config = bot_config.read().await;
 
-    let owner_id = bot_config.owner_id;
+    let mut explanation = PermissionExplanation {
+        permission: BotPermission::None,
+        sources: Vec::new(),
+        winner: None,
+    };
 
-    if entity_id == owner_id{
-        return Ok(BotPermission::Owner);
+    if bot_config.is_owner(user){
+        explanation.consider(PermissionSource::Owner, Some(BotPermission::Owner));
+        return Ok(explanation);
     }
+    explanation.consider(PermissionSource::Owner, None);
 
-    let mut ret_perm = BotPermission::None;
-
-    if let Some(guild) = msg.guild(ctx){
+    if let Some(guild) = guild.and_then(|guild| guild.to_guild_cached(ctx)){
         if let Some(guild_cfg) = bot_config.server_cfgs.get(&guild.id){
             // check if all users default to User permission
-            if guild_cfg.user_default{
-                ret_perm = BotPermission::User;
-            }
+            explanation.consider(PermissionSource::UserDefault, guild_cfg.user_default.then_some(BotPermission::User));
+
+            explanation.consider(PermissionSource::Everyone, guild_cfg.entity_permission.get(&EntityId::Everyone).copied());
 
             // check if user has a permission assigned
-            if let Some(perm) = guild_cfg.entity_permission.get(&entity_id){
-                if perm.dominates(&ret_perm){
-                    ret_perm = *perm;
-                }
-            }
+            explanation.consider(PermissionSource::User(user), guild_cfg.entity_permission.get(&EntityId::User(user)).copied());
 
             // check if user has a role with sufficient permission assigned
-            if let Ok(mem) = guild.member(ctx, entity_id).await{
-                for role in mem.roles{
-                    if let Some(perm) = guild_cfg.entity_permission.get(&role.into()){ // TODO fix when "impl trait aliases" are stable
-                        if perm.dominates(&ret_perm){
-                            ret_perm = *perm;
+            if let Ok(mem) = guild.member(ctx, user).await{
+                for role in &mem.roles{
+                    explanation.consider(PermissionSource::Role(*role), guild_cfg.entity_permission.get(&EntityId::Role(*role)).copied());
+                }
+
+                // check if the Discord permissions of the user are mapped
+                if !guild_cfg.native_permissions.is_empty() {
+                    if let Ok(permissions) = mem.permissions(ctx) {
+                        for (native, perm) in &guild_cfg.native_permissions {
+                            explanation.consider(PermissionSource::Native(*native), permissions.contains(native.permissions()).then_some(*perm));
                         }
                     }
                 }
             }
+
+            // check if the channel or the voice channel shared with the bot has a permission assigned
+            explanation.consider(PermissionSource::Channel(channel), guild_cfg.entity_permission.get(&EntityId::Channel(channel)).copied());
+            if let Some(voice_channel) = shared_voice_channel(ctx, &guild, user){
+                explanation.consider(PermissionSource::VoiceChannel(voice_channel), guild_cfg.entity_permission.get(&EntityId::Channel(voice_channel)).copied());
+            }
         }else{
             return Err(Reason::User("Server config doesnt exist!".to_string()))
         }
     }
 
-    return Ok(ret_perm);
+    Ok(explanation)
+}
+
+pub async fn user_permission(ctx: &Context, msg: &Message, user: UserId) -> Result<BotPermission, Reason>{
+    user_permission_in(ctx, msg.guild_id, msg.channel_id, user).await
+}
+
+pub async fn user_permission_in(ctx: &Context, guild: Option<GuildId>, channel: ChannelId, user: UserId) -> Result<BotPermission, Reason>{
+    explain_permission_in(ctx, guild, channel, user).await.map(|explanation| explanation.permission)
+}
+
+/// Returns the permission a command requires without guild overrides, i.e. the highest
+/// `verify_*` check the command or its group is declared with.
+pub fn default_command_permission(group: &CommandGroup, command: &CommandOptions) -> Option<BotPermission>{
+    let checks: [(&Check, BotPermission); 4] = [
+        (&VERIFY_OWNER_CHECK, BotPermission::Owner),
+        (&VERIFY_ADMIN_CHECK, BotPermission::Admin),
+        (&VERIFY_MODERATOR_CHECK, BotPermission::Moderator),
+        (&VERIFY_USER_CHECK, BotPermission::User),
+    ];
+    group.options.checks.iter()
+         .chain(command.checks.iter())
+         .filter_map(|check| checks.iter().find(|(verify, _)| std::ptr::eq(*check, *verify)).map(|(_, perm)| *perm))
+         .max_by_key(BotPermission::level)
 }
 
-async fn verify_permission(ctx: &Context, msg: &Message, command_permission: BotPermission) -> Result<(), Reason>{
+/// Returns the highest default permission of the commands of a group.
+pub fn default_group_permission(group: &'static CommandGroup) -> Option<BotPermission>{
+    crate::commands::group_commands(group).iter()
+                                          .filter_map(|command| default_command_permission(group, command.options))
+                                          .max_by_key(BotPermission::level)
+}
+
+/// Returns the modules a group requires one of, i.e. the `*_module` checks it is declared with.
+pub fn group_modules(group: &CommandGroup) -> &'static [BotModes]{
+    let checks: [(&Check, &'static [BotModes]); 4] = [
+        (&LATEX_MODULE_CHECK, &[BotModes::Latex]),
+        (&MUSIC_MODULE_CHECK, &[BotModes::Music]),
+        (&SOUNDBOARD_MODULE_CHECK, &[BotModes::Soundboard]),
+        (&AUDIO_MODULE_CHECK, &[BotModes::Music, BotModes::Soundboard]),
+    ];
+    group.options.checks.iter()
+         .find_map(|check| checks.iter().find(|(module, _)| std::ptr::eq(*check, *module)).map(|(_, modules)| *modules))
+         .unwrap_or(&[])
+}
+
+/// Blocked users are ignored without a reply, `dispatch_error` only answers `Reason::User`.
+fn blocked_reason(user: UserId) -> Reason {
+    Reason::Log(format!("Ignored blocked user {}", user))
+}
+
+/// Checks the author of a command against the permission and channel rules of the guild, see
+/// `ConfigStruct::command_access`.
+pub async fn check_command_access(ctx: &Context, guild: Option<GuildId>, channel: ChannelId, author: UserId, options: &CommandOptions) -> Result<(), Reason>{
+    let group = crate::commands::command_group(options);
+    let command = crate::commands::command_key(group, options);
+    let default_permission = group.and_then(|group| default_command_permission(group, options));
+    let group = group.map(|group| group.name);
+
+    let permission = user_permission_in(ctx, guild, channel, author).await?;
+    if permission == BotPermission::Blocked {
+        return Err(blocked_reason(author));
+    }
 
-    if user_permission(ctx, msg, msg.author.id).await?.dominates(&command_permission){
+    let data = ctx.data.read().await;
+    let bot_config = match data.get::<BotConfig>() {
+        Some(v) => v,
+        None => {
+            return Err(Reason::User("Bot config failed!".to_string()));
+        },
+    };
+    let bot_config = bot_config.read().await;
+
+    let channels = command_channels(ctx, guild, channel, author);
+    bot_config.command_access(guild, &command, group, default_permission, permission, &channels).map_err(Reason::User)
+}
+
+/// Applies `check_command_access` to every command, each group declares it before its module checks.
+#[check]
+#[display_in_help(false)]
+async fn command_access(ctx: &Context, msg: &Message, _args: &mut Args, options: &CommandOptions) -> Result<(), Reason>{
+    check_command_access(ctx, msg.guild_id, msg.channel_id, msg.author.id, options).await
+}
+
+// The `verify_*` checks only declare the default permission of a command for
+// `default_command_permission`, `command_access` enforces it.
+
+#[check]
+async fn verify_owner() -> Result<(), Reason>{
+    Ok(())
+}
+
+#[check]
+async fn verify_admin() -> Result<(), Reason>{
+    Ok(())
+}
+
+#[check]
+async fn verify_moderator() -> Result<(), Reason>{
+    Ok(())
+}
+
+#[check]
+async fn verify_user() -> Result<(), Reason>{
+    Ok(())
+}
+
+
+async fn verify_module(ctx: &Context, msg: &Message, modules: &[BotModes]) -> Result<(), Reason>{
+    // blocked users were already turned away by `command_access`, declared before the module checks
+    check_modules(ctx, msg.guild_id, modules).await
+}
+
+/// Checks that one of the modules is enabled in the guild.
+pub async fn check_modules(ctx: &Context, guild: Option<GuildId>, modules: &[BotModes]) -> Result<(), Reason>{
+    if modules.is_empty() {
+        return Ok(());
+    }
+
+    let data = ctx.data.read().await;
+    let bot_config = match data.get::<BotConfig>() {
+        Some(v) => v,
+        None => {
+            return Err(Reason::User("Bot config failed!".to_string()));
+        },
+    };
+    let bot_config = bot_config.read().await;
+
+    if modules.iter().any(|module| bot_config.guild_module_enabled(guild, *module)){
         return Ok(());
     }
 
-    Err(Reason::User("Insufficient Permission!".to_string()))
+    let names: Vec<String> = modules.iter().map(|module| format!("{:?}", module)).collect();
+    Err(Reason::User(format!("The {} module is disabled on this server!", names.join("/"))))
 }
 
+/// Limits a group to the owners like `#[owners_only]`, but reads them from the config so
+/// reloads apply and guild permission overrides can't lift it.
 #[check]
-async fn verify_owner(ctx: &Context, msg: &Message) -> Result<(), Reason>{
-    verify_permission(ctx, msg, BotPermission::Owner).await
+#[display_in_help(false)]
+async fn owners_only(ctx: &Context, msg: &Message) -> Result<(), Reason>{
+    let data = ctx.data.read().await;
+    let bot_config = match data.get::<BotConfig>() {
+        Some(v) => v,
+        None => {
+            return Err(Reason::User("Bot config failed!".to_string()));
+        },
+    };
+    if bot_config.read().await.is_owner(msg.author.id) {
+        Ok(())
+    } else {
+        Err(Reason::User("Only owners can use this command!".to_string()))
+    }
 }
 
 #[check]
-async fn verify_admin(ctx: &Context, msg: &Message) -> Result<(), Reason>{
-    verify_permission(ctx, msg, BotPermission::Admin).await
+#[display_in_help(false)]
+async fn latex_module(ctx: &Context, msg: &Message) -> Result<(), Reason>{
+    verify_module(ctx, msg, &[BotModes::Latex]).await
 }
 
 #[check]
-async fn verify_moderator(ctx: &Context, msg: &Message) -> Result<(), Reason>{
-    verify_permission(ctx, msg, BotPermission::Moderator).await
+#[display_in_help(false)]
+async fn music_module(ctx: &Context, msg: &Message) -> Result<(), Reason>{
+    verify_module(ctx, msg, &[BotModes::Music]).await
 }
 
 #[check]
-async fn verify_user(ctx: &Context, msg: &Message) -> Result<(), Reason>{
-    verify_permission(ctx, msg, BotPermission::User).await
+#[display_in_help(false)]
+async fn soundboard_module(ctx: &Context, msg: &Message) -> Result<(), Reason>{
+    verify_module(ctx, msg, &[BotModes::Soundboard]).await
+}
+
+#[check]
+#[display_in_help(false)]
+async fn audio_module(ctx: &Context, msg: &Message) -> Result<(), Reason>{
+    verify_module(ctx, msg, &[BotModes::Music, BotModes::Soundboard]).await
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::model::id::{ChannelId, GuildId, RoleId, UserId};
+    use super::{parse_config_value, BotPermission, ConfigStruct, EntityId, ServerAudioStruct, COMMAND_ACCESS_CHECK, CONFIG_VERSION};
+    use super::BotPermission::*;
+
+    const ALL: [BotPermission; 6] = [Owner, Admin, Moderator, User, None, Blocked];
+
+    #[test]
+    fn dominates_matrix() {
+        // rows dominate columns, in the order of `ALL`
+        let expected = [
+            [true,  true,  true,  true,  true,  true ],
+            [false, true,  true,  true,  true,  true ],
+            [false, false, true,  true,  true,  true ],
+            [false, false, false, true,  true,  true ],
+            [false, false, false, false, true,  true ],
+            [false, false, false, false, false, true ],
+        ];
+        for (row, perm) in ALL.iter().enumerate() {
+            for (column, other) in ALL.iter().enumerate() {
+                assert_eq!(perm.dominates(other), expected[row][column], "{:?} dominates {:?}", perm, other);
+            }
+        }
+    }
+
+    #[test]
+    fn may_change_below_own_rank() {
+        assert!(Admin.may_change(&User, &Moderator).is_ok());
+        assert!(Admin.may_change(&Moderator, &None).is_ok());
+        assert!(Moderator.may_change(&None, &User).is_ok());
+        assert!(Moderator.may_change(&User, &Blocked).is_ok());
+        assert!(Owner.may_change(&Admin, &None).is_ok());
+    }
+
+    #[test]
+    fn may_change_grants_up_to_own_rank() {
+        assert!(Admin.may_change(&User, &Admin).is_ok());
+        assert!(Moderator.may_change(&None, &Moderator).is_ok());
+    }
+
+    #[test]
+    fn may_change_rejects_promotion_above_self() {
+        assert!(Moderator.may_change(&User, &Admin).is_err());
+        assert!(Admin.may_change(&Moderator, &Owner).is_err());
+        assert!(User.may_change(&None, &Moderator).is_err());
+    }
+
+    #[test]
+    fn may_change_rejects_demoting_an_equal() {
+        for perm in [Admin, Moderator, User] {
+            assert!(perm.may_change(&perm, &None).is_err(), "{:?} demotes {:?}", perm, perm);
+        }
+    }
+
+    #[test]
+    fn may_change_rejects_touching_owners() {
+        for perm in ALL {
+            assert!(perm.may_change(&Owner, &None).is_err(), "{:?} demotes an owner", perm);
+            assert!(perm.may_change(&Owner, &Admin).is_err(), "{:?} changes an owner", perm);
+        }
+    }
+
+    #[test]
+    fn native_permissions_need_owner_for_admin() {
+        let mut server = ServerAudioStruct::default();
+        assert!(server.may_set_native_permissions(Admin, "on").is_err());
+        assert!(server.may_set_native_permissions(Admin, "manage_messages=moderator").is_err());
+        assert!(server.may_set_native_permissions(Owner, "on").is_ok());
+        assert!(server.may_set_native_permissions(Admin, "off").is_ok());
+
+        server.set_setting("native_permissions", "manage_messages=moderator").unwrap();
+        assert!(server.may_set_native_permissions(Admin, "manage_messages=user").is_ok());
+        assert!(server.may_set_native_permissions(Admin, "manage_guild=admin").is_err());
+        assert!(server.may_set_native_permissions(Admin, "off").is_ok());
+    }
+
+    #[test]
+    fn import_cannot_escalate_permissions() {
+        let mut current = ServerAudioStruct::default();
+        current.insert_entity_permission(UserId(1), Admin);
+        current.insert_entity_permission(RoleId(2), Moderator);
+
+        let mut imported = current.clone();
+        assert!(current.may_replace_permissions(Admin, &imported).is_ok());
+        imported.insert_entity_permission(UserId(3), Owner);
+        assert!(current.may_replace_permissions(Admin, &imported).is_err());
+        assert!(current.may_replace_permissions(Owner, &imported).is_ok());
+
+        let mut imported = current.clone();
+        imported.insert_entity_permission(EntityId::Everyone, Admin);
+        assert!(current.may_replace_permissions(Admin, &imported).is_err());
+
+        let mut imported = current.clone();
+        imported.insert_entity_permission(UserId(1), None);
+        assert!(current.may_replace_permissions(Admin, &imported).is_err());
+        imported.insert_entity_permission(UserId(1), Admin);
+        imported.insert_entity_permission(RoleId(2), User);
+        assert!(current.may_replace_permissions(Admin, &imported).is_ok());
+    }
+
+    #[test]
+    fn reset_keeps_grants_above_the_caller() {
+        let mut server = ServerAudioStruct::default();
+        server.insert_entity_permission(UserId(1), Owner);
+        server.insert_entity_permission(UserId(2), Admin);
+        server.insert_entity_permission(UserId(3), Moderator);
+        server.insert_entity_permission(RoleId(4), Blocked);
+        server.set_setting("prefix", "!").unwrap();
+
+        let reset = server.reset(Admin);
+        assert_eq!(reset.entity_permissions().len(), 2);
+        assert_eq!(reset.entity_permissions().get(&EntityId::User(UserId(1))), Some(&Owner));
+        assert_eq!(reset.entity_permissions().get(&EntityId::User(UserId(2))), Some(&Admin));
+        assert_eq!(reset.get_setting("prefix"), ServerAudioStruct::default().get_setting("prefix"));
+
+        let reset = server.reset(Owner);
+        assert_eq!(reset.entity_permissions().len(), 1);
+        assert_eq!(reset.entity_permissions().get(&EntityId::User(UserId(1))), Some(&Owner));
+    }
+
+    #[test]
+    fn every_group_checks_command_access() {
+        for group in crate::commands::all_groups() {
+            assert!(group.options.checks.iter().any(|check| std::ptr::eq(*check, &COMMAND_ACCESS_CHECK)),
+                    "{} does not check command_access", group.name);
+        }
+    }
+
+    #[test]
+    fn overrides_apply_to_unchecked_commands() {
+        let guild = GuildId(1);
+        let mut cfg = ConfigStruct::default();
+        cfg.init_server(guild);
+        assert!(cfg.command_access(Some(guild), "whoami", Some("General"), Option::None, None, &[]).is_ok());
+
+        cfg.set_command_permission(guild, "whoami", Some(Moderator));
+        assert!(cfg.command_access(Some(guild), "whoami", Some("General"), Option::None, User, &[]).is_err());
+        assert!(cfg.command_access(Some(guild), "whoami", Some("General"), Option::None, Moderator, &[]).is_ok());
+
+        cfg.set_command_permission(guild, "latex", Some(User));
+        assert!(cfg.command_access(Some(guild), "tex", Some("Latex"), Option::None, None, &[]).is_err());
+        assert!(cfg.command_access(Some(guild), "tex", Some("Latex"), Option::None, User, &[]).is_ok());
+    }
+
+    #[test]
+    fn overrides_replace_the_declared_permission() {
+        let guild = GuildId(1);
+        let mut cfg = ConfigStruct::default();
+        cfg.init_server(guild);
+        assert!(cfg.command_access(Some(guild), "config set", Some("Config"), Some(Admin), Moderator, &[]).is_err());
+
+        cfg.set_command_permission(guild, "config", Some(Moderator));
+        assert!(cfg.command_access(Some(guild), "config set", Some("Config"), Some(Admin), Moderator, &[]).is_ok());
+        cfg.set_command_permission(guild, "config set", Some(Owner));
+        assert!(cfg.command_access(Some(guild), "config set", Some("Config"), Some(Admin), Admin, &[]).is_err());
+    }
+
+    #[test]
+    fn channel_restrictions_apply_to_unchecked_commands() {
+        let guild = GuildId(1);
+        let (allowed, other) = (ChannelId(10), ChannelId(11));
+        let mut cfg = ConfigStruct::default();
+        cfg.init_server(guild);
+        assert!(cfg.command_access(Some(guild), "math", Some("Latex"), Option::None, None, &[other]).is_ok());
+
+        cfg.restrict_command_channel(guild, "math", allowed, true);
+        assert!(cfg.command_access(Some(guild), "math", Some("Latex"), Option::None, None, &[allowed]).is_ok());
+        assert!(cfg.command_access(Some(guild), "math", Some("Latex"), Option::None, Admin, &[other]).is_err());
+        assert!(cfg.command_access(Some(guild), "math", Some("Latex"), Option::None, Owner, &[other]).is_ok());
+
+        cfg.restrict_command_channel(guild, "latex", other, false);
+        assert!(cfg.command_access(Some(guild), "tex", Some("Latex"), Option::None, User, &[other]).is_err());
+        assert!(cfg.command_access(Some(guild), "tex", Some("Latex"), Option::None, User, &[allowed]).is_ok());
+    }
+
+    const V0_CONFIG: &str = "
+owner_id: 1
+prefix: \"~\"
+auto_reconnect: true
+bot_mode: All
+activity: 0
+server_cfgs:
+  100:
+    volume: 50
+    auto_playlist: false
+    user_default: false
+    entity_permission:
+      200: Admin
+      201: Blocked
+";
+
+    const V1_CONFIG: &str = "
+version: 1
+owner_id: 1
+prefix: \"~\"
+auto_reconnect: true
+bot_mode: All
+activity: 0
+server_cfgs:
+  100:
+    volume: 50
+    auto_playlist: false
+    user_default: true
+    entity_permission:
+      200: Moderator
+archived_server_cfgs:
+  101:
+    volume: 80
+    auto_playlist: false
+    user_default: false
+    entity_permission:
+      300: User
+";
+
+    const V2_CONFIG: &str = "
+version: 2
+owner_id: 1
+prefix: \"~\"
+auto_reconnect: true
+bot_mode: All
+activity: 0
+server_cfgs:
+  100:
+    volume: 50
+    auto_playlist: false
+    user_default: false
+    entity_permission:
+      user:200: Admin
+      role:201: Moderator
+      everyone: User
+";
+
+    fn parse(config: &str) -> ConfigStruct {
+        parse_config_value(serde_yaml::from_str(config).unwrap()).unwrap()
+    }
+
+    fn entity_permissions(cfg: &ConfigStruct, guild: u64) -> Vec<(EntityId, BotPermission)> {
+        let mut entities: Vec<(EntityId, BotPermission)> = cfg.server_config(GuildId(guild)).unwrap().0
+                                                              .entity_permissions().iter()
+                                                              .map(|(entity, perm)| (*entity, *perm))
+                                                              .collect();
+        entities.sort_by_key(|(entity, _)| *entity);
+        entities
+    }
+
+    #[test]
+    fn migrates_v0_ids_to_legacy_entities() {
+        let cfg = parse(V0_CONFIG);
+        assert_eq!(cfg.version, CONFIG_VERSION);
+        assert_eq!(entity_permissions(&cfg, 100), vec![(EntityId::Legacy(200), Admin), (EntityId::Legacy(201), Blocked)]);
+    }
+
+    #[test]
+    fn migrates_v1_ids_to_legacy_entities() {
+        let cfg = parse(V1_CONFIG);
+        assert_eq!(cfg.version, CONFIG_VERSION);
+        assert_eq!(entity_permissions(&cfg, 100), vec![(EntityId::Legacy(200), Moderator)]);
+        assert_eq!(entity_permissions(&cfg, 101), vec![(EntityId::Legacy(300), User)]);
+        assert!(cfg.server_config(GuildId(101)).unwrap().1);
+    }
+
+    #[test]
+    fn keeps_typed_entities_of_current_configs() {
+        let cfg = parse(V2_CONFIG);
+        assert_eq!(entity_permissions(&cfg, 100), vec![
+            (EntityId::User(UserId(200)), Admin),
+            (EntityId::Role(RoleId(201)), Moderator),
+            (EntityId::Everyone, User),
+        ]);
+    }
+
+    #[test]
+    fn rejects_configs_from_newer_versions() {
+        let config = V2_CONFIG.replace("version: 2", &format!("version: {}", CONFIG_VERSION + 1));
+        assert!(parse_config_value(serde_yaml::from_str(&config).unwrap()).is_err());
+    }
+
+    const ENTITIES: [EntityId; 5] = [
+        EntityId::User(UserId(1)),
+        EntityId::Role(RoleId(2)),
+        EntityId::Channel(ChannelId(3)),
+        EntityId::Everyone,
+        EntityId::Legacy(4),
+    ];
+
+    #[test]
+    fn entity_ids_round_trip_as_text() {
+        for entity in ENTITIES {
+            assert_eq!(entity.to_string().parse::<EntityId>(), Ok(entity));
+        }
+        assert_eq!("role:2".parse::<EntityId>(), Ok(EntityId::Role(RoleId(2))));
+        assert!("2".parse::<EntityId>().is_err());
+        assert!("role:x".parse::<EntityId>().is_err());
+        assert!("guild:2".parse::<EntityId>().is_err());
+    }
+
+    #[test]
+    fn entity_ids_round_trip_as_yaml_keys() {
+        let mut server = ServerAudioStruct::default();
+        for (entity, perm) in ENTITIES.iter().zip([Admin, Moderator, User, User, Blocked]) {
+            server.insert_entity_permission(*entity, perm);
+        }
+        let yaml = serde_yaml::to_string(&server).unwrap();
+        let parsed: ServerAudioStruct = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(parsed.entity_permissions(), server.entity_permissions());
+    }
 }
@@ -0,0 +1,98 @@
+use std::path::PathBuf;
+
+pub const USAGE: &str = "Usage: discord_bot [OPTIONS]
+
+Options:
+    --config <path>        Config file (env BOT_CONFIG, default ./bot_config.yml)
+    --credentials <path>   Credentials file (env BOT_CREDENTIALS, default ./bot_credentials.yml)
+    --data-dir <path>      Directory for backups and other data (env BOT_DATA_DIR, default .)
+    --storage <backend>    Config storage, yaml or sqlite (env BOT_STORAGE, default yaml)
+    --log-level <filter>   Log level or RUST_LOG style directives (env BOT_LOG, default info)
+    --log-format <format>  Log format, text or json (env BOT_LOG_FORMAT, default text)
+    --log-dir <path>       Also log to daily rotated files in this directory (env BOT_LOG_DIR)
+    --import-config <path> Import a YAML config into the configured storage, then exit
+    --check-config         Validate credentials and config, then exit
+    --help                 Print this help
+
+The token is taken from the DISCORD_TOKEN environment variable if it is set.";
+
+#[derive(Debug)]
+pub struct CliOptions {
+    pub config_path: PathBuf,
+    pub credentials_path: PathBuf,
+    pub data_dir: PathBuf,
+    pub storage: String,
+    pub log_level: String,
+    pub log_json: bool,
+    pub log_dir: Option<PathBuf>,
+    pub import_config: Option<PathBuf>,
+    pub check_config: bool,
+    pub help: bool,
+}
+
+fn parse_log_format(format: &str) -> Result<bool, String> {
+    match format {
+        "text" => Ok(false),
+        "json" => Ok(true),
+        _ => Err(format!("Unknown log format `{}`!", format)),
+    }
+}
+
+fn env_path(key: &str, default: &str) -> PathBuf {
+    match std::env::var(key) {
+        Ok(value) if !value.is_empty() => PathBuf::from(value),
+        _ => PathBuf::from(default),
+    }
+}
+
+impl Default for CliOptions {
+    fn default() -> Self {
+        CliOptions {
+            config_path: env_path("BOT_CONFIG", "bot_config.yml"),
+            credentials_path: env_path("BOT_CREDENTIALS", "bot_credentials.yml"),
+            data_dir: env_path("BOT_DATA_DIR", "."),
+            storage: std::env::var("BOT_STORAGE").unwrap_or_else(|_| "yaml".to_string()),
+            log_level: std::env::var("BOT_LOG").unwrap_or_else(|_| "info".to_string()),
+            log_json: false,
+            log_dir: std::env::var("BOT_LOG_DIR").ok().filter(|dir| !dir.is_empty()).map(PathBuf::from),
+            import_config: None,
+            check_config: false,
+            help: false,
+        }
+    }
+}
+
+/// Parses the command line arguments, flags take precedence over environment variables.
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> Result<CliOptions, String> {
+    let mut options = CliOptions::default();
+    if let Ok(format) = std::env::var("BOT_LOG_FORMAT") {
+        options.log_json = parse_log_format(&format)?;
+    }
+
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let mut value = |flag: &str| {
+            inline_value.clone()
+                        .or_else(|| args.next())
+                        .ok_or_else(|| format!("Missing value for `{}`!", flag))
+        };
+
+        match flag.as_str() {
+            "--config" => options.config_path = PathBuf::from(value(&flag)?),
+            "--credentials" => options.credentials_path = PathBuf::from(value(&flag)?),
+            "--data-dir" => options.data_dir = PathBuf::from(value(&flag)?),
+            "--storage" => options.storage = value(&flag)?,
+            "--log-level" => options.log_level = value(&flag)?,
+            "--log-format" => options.log_json = parse_log_format(&value(&flag)?)?,
+            "--log-dir" => options.log_dir = Some(PathBuf::from(value(&flag)?)),
+            "--import-config" => options.import_config = Some(PathBuf::from(value(&flag)?)),
+            "--check-config" => options.check_config = true,
+            "--help" | "-h" => options.help = true,
+            _ => return Err(format!("Unknown argument `{}`!", flag)),
+        }
+    }
+    Ok(options)
+}
@@ -6,13 +6,17 @@ use serenity::prelude::TypeMapKey;
 use serenity::framework::standard::{Args, CommandResult};
 use serenity::framework::standard::macros::{command, group};
 use serenity::model::channel::Message;
-use serenity::model::id::GuildId;
+use serenity::model::guild::Guild;
+use serenity::model::id::{GuildId, UserId};
+use tracing::error;
 
+use crate::audit_utils::{audit, AuditEntry};
 use crate::bot_utils::*;
 
 #[group]
 //#[summary = "Audio commands"]
 #[commands(deafen, join, leave, mute, undeafen, unmute, set_volume)]
+#[checks(command_access, audio_module)]
 pub struct Audio;
 
 pub mod music;
@@ -59,25 +63,29 @@ pub async fn deafen(ctx: &Context, msg: &Message) -> CommandResult {
 
 pub async fn join_channel(ctx: &Context, msg: &Message) -> CommandResult {
     let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
 
+    if let Err(why) = join_voice(ctx, &guild, msg.author.id).await {
+        check_msg(msg.reply(ctx, why).await);
+    }
+
+    Ok(())
+}
+
+/// Joins the voice channel the user is in.
+pub async fn join_voice(ctx: &Context, guild: &Guild, user: UserId) -> Result<(), &'static str> {
     let channel_id = guild
-        .voice_states.get(&msg.author.id)
+        .voice_states.get(&user)
         .and_then(|voice_state| voice_state.channel_id);
 
     let connect_to = match channel_id {
         Some(channel) => channel,
-        None => {
-            check_msg(msg.reply(ctx, "Not in a voice channel").await);
-
-            return Ok(());
-        }
+        None => return Err("Not in a voice channel"),
     };
 
     let manager = songbird::get(ctx).await
         .expect("Songbird Voice client placed in at initialisation.").clone();
 
-    let _handler = manager.join(guild_id, connect_to).await;
+    let _handler = manager.join(guild.id, connect_to).await;
 
     Ok(())
 }
@@ -96,23 +104,30 @@ pub async fn join(ctx: &Context, msg: &Message) -> CommandResult {
 #[checks(verify_user)]
 pub async fn leave(ctx: &Context, msg: &Message) -> CommandResult {
     let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
 
+    match leave_voice(ctx, guild.id).await {
+        Ok(reply) => check_msg(msg.channel_id.say(&ctx.http, reply).await),
+        Err(why) => check_msg(msg.reply(ctx, why).await),
+    }
+
+    Ok(())
+}
+
+/// Leaves the voice channel of the guild.
+pub async fn leave_voice(ctx: &Context, guild_id: GuildId) -> Result<String, String> {
     let manager = songbird::get(ctx).await
         .expect("Songbird Voice client placed in at initialisation.").clone();
     let has_handler = manager.get(guild_id).is_some();
 
     if has_handler {
         if let Err(e) = manager.remove(guild_id).await {
-            check_msg(msg.channel_id.say(&ctx.http, format!("Failed: {:?}", e)).await);
+            return Ok(format!("Failed: {:?}", e));
         }
 
-        check_msg(msg.channel_id.say(&ctx.http, "Left voice channel").await);
+        Ok("Left voice channel".to_string())
     } else {
-        check_msg(msg.reply(ctx, "Not in a voice channel").await);
+        Err("Not in a voice channel".to_string())
     }
-
-    Ok(())
 }
 
 #[command]
@@ -219,17 +234,6 @@ async fn get_volume(ctx: &Context, guild: GuildId) -> u8{
 #[usage("Values from 10..100 are allowed.")]
 #[checks(verify_moderator)]
 pub async fn set_volume(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
-    let data = ctx.data.write().await;
-
-    let bot_config = match data.get::<BotConfig>() {
-        Some(v) => v,
-        None => {
-            msg.reply(ctx, "There was a problem getting the bot config!").await.unwrap();
-            return Ok(());
-        },
-    };
-    let mut bot_config = bot_config.write().await;
-
     let volume = match args.single::<u8>() {
         Ok(vol) => vol,
         Err(_) => {
@@ -238,13 +242,37 @@ pub async fn set_volume(ctx: &Context, msg: &Message, mut args: Args) -> Command
         },
     };
     if let Some(guild) = msg.guild_id{
-        bot_config.set_guild_volume(guild, volume);
-        let players = data.get::<Player>().expect("Expected Player in TypeMap.");
-        if let Some(track_handler) = players.get(&guild){
-            track_handler.set_volume((volume as f32)/100f32).expect("Can not set volume!");
+        if let Err(why) = change_volume(ctx, guild, msg.author.id, volume).await {
+            check_msg(msg.channel_id.say(&ctx.http, why).await);
         }
     }
-    write_config(&bot_config).expect("Config could not be written!");
 
     Ok(())
+}
+
+/// Sets and persists the volume of the guild, applying it to the current track.
+/// Returns the volume after clamping it to 10..100.
+pub async fn change_volume(ctx: &Context, guild: GuildId, author: UserId, volume: u8) -> Result<u8, &'static str> {
+    let data = ctx.data.write().await;
+
+    let bot_config = match data.get::<BotConfig>() {
+        Some(v) => v,
+        None => return Err("There was a problem getting the bot config!"),
+    };
+    let mut bot_config = bot_config.write().await;
+
+    let old = bot_config.get_guild_volume(guild);
+    bot_config.set_guild_volume(guild, volume);
+    let volume = bot_config.get_guild_volume(guild);
+    let players = data.get::<Player>().expect("Expected Player in TypeMap.");
+    if let Some(track_handler) = players.get(&guild){
+        track_handler.set_volume((volume as f32)/100f32).expect("Can not set volume!");
+    }
+    if let Err(why) = write_guild_config(&bot_config, guild) {
+        error!("Config could not be written: {:?}", why);
+        return Err("Config could not be written!");
+    }
+    audit(ctx, bot_config.get_guild_log_channel(guild), AuditEntry::new(guild, author, "volume", old.to_string(), volume.to_string())).await;
+
+    Ok(volume)
 }
\ No newline at end of file
@@ -2,13 +2,17 @@ use serenity::client::Context;
 use serenity::framework::standard::{Args, CommandResult};
 use serenity::framework::standard::macros::{command, group};
 use serenity::model::channel::Message;
+use serenity::model::id::GuildId;
+use tracing::warn;
 
+use crate::audit_utils::{audit, AuditEntry};
 use crate::bot_utils::*;
 use crate::commands::audio::{get_volume, Player};
 
 #[group]
 //#[summary = "Music commands"]
 #[commands(play, resume, stop, pause, set_auto_playlist)]
+#[checks(command_access, music_module)]
 pub struct Music;
 
 #[command]
@@ -24,14 +28,20 @@ pub async fn play(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult
         },
     };
 
-    if !url.starts_with("http") {
-        check_msg(msg.channel_id.say(&ctx.http, "Must provide a valid URL").await);
-
-        return Ok(());
+    let guild = msg.guild(&ctx.cache).unwrap();
+    match play_url(ctx, guild.id, &url).await {
+        Ok(reply) | Err(reply) => check_msg(msg.channel_id.say(&ctx.http, reply).await),
     }
 
-    let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
+    Ok(())
+}
+
+/// Plays the audio of a URL in the voice channel the bot is connected to in the guild,
+/// replacing the current track. Returns the reply for the user, as error if nothing plays.
+pub async fn play_url(ctx: &Context, guild_id: GuildId, url: &str) -> Result<&'static str, &'static str> {
+    if !url.starts_with("http") {
+        return Err("Must provide a valid URL");
+    }
 
     let manager = songbird::get(ctx).await
         .expect("Songbird Voice client placed in at initialisation.").clone();
@@ -39,14 +49,12 @@ pub async fn play(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult
     if let Some(handler_lock) = manager.get(guild_id) {
         let mut handler = handler_lock.lock().await;
 
-        let source = match songbird::ytdl(&url).await {
+        let source = match songbird::ytdl(url).await {
             Ok(source) => source,
             Err(why) => {
-                println!("Err starting source: {:?}", why);
-
-                check_msg(msg.channel_id.say(&ctx.http, "Error sourcing ffmpeg").await);
+                warn!("Err starting source: {:?}", why);
 
-                return Ok(());
+                return Err("Error sourcing ffmpeg");
             },
         };
 
@@ -61,12 +69,10 @@ pub async fn play(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult
             handle_map.stop().expect("Could not stop previews track!");
         }
         handler.play(track);
-        check_msg(msg.channel_id.say(&ctx.http, "Playing song").await);
+        Ok("Playing song")
     } else {
-        check_msg(msg.channel_id.say(&ctx.http, "Not in a voice channel to play in").await);
+        Err("Not in a voice channel to play in")
     }
-
-    Ok(())
 }
 
 #[command]
@@ -74,18 +80,23 @@ pub async fn play(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult
 #[checks(verify_user)]
 pub async fn stop(ctx: &Context, msg: &Message) -> CommandResult {
     let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
+    match stop_track(ctx, guild.id).await {
+        Ok(reply) | Err(reply) => check_msg(msg.channel_id.say(&ctx.http, reply).await),
+    }
+
+    Ok(())
+}
 
+/// Stops the current track of the guild. Returns the reply for the user, as error if nothing played.
+pub async fn stop_track(ctx: &Context, guild_id: GuildId) -> Result<&'static str, &'static str> {
     let mut data = ctx.data.write().await;
     let players = data.get_mut::<Player>().expect("Expected Player in TypeMap.");
     if let Some(track_handler) = players.remove(&guild_id){
         track_handler.stop().expect("Can not stop!");
-        check_msg(msg.channel_id.say(&ctx.http, "Stopping song").await);
+        Ok("Stopping song")
     }else{
-        check_msg(msg.channel_id.say(&ctx.http, "No song to stop").await);
+        Err("No song to stop")
     }
-
-    Ok(())
 }
 
 #[command]
@@ -149,9 +160,13 @@ pub async fn set_auto_playlist(ctx: &Context, msg: &Message, mut args: Args) ->
         },
     };
     if let Some(guild) = msg.guild_id{
+        let old = bot_config.get_guild_auto_playlist(guild);
         bot_config.set_guild_auto_playlist(guild, setting);
+        if check_write(ctx, msg, write_guild_config(&bot_config, guild)).await {
+            let entry = AuditEntry::new(guild, msg.author.id, "auto_playlist", old.to_string(), setting.to_string());
+            audit(ctx, bot_config.get_guild_log_channel(guild), entry).await;
+        }
     }
-    write_config(&bot_config).expect("Config could not be written!");
 
     Ok(())
 }
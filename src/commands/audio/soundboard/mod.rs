@@ -2,52 +2,72 @@ use serenity::client::Context;
 use serenity::framework::standard::{Args, CommandResult};
 use serenity::framework::standard::macros::{command, group};
 use serenity::model::channel::Message;
+use serenity::model::id::GuildId;
+use tracing::warn;
 
 use crate::bot_utils::*;
 
 #[group]
 //#[summary = "Soundboard commands"]
 #[commands(sb)]
+#[checks(command_access, soundboard_module)]
 pub struct Soundboard;
 
 #[command]
 #[only_in(guilds)]
+#[usage("<path relative to the sound directory>")]
 #[checks(verify_user)]
 pub async fn sb(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
     let path = match args.single::<String>() {
         Ok(path) => path,
         Err(_) => {
-            check_msg(msg.channel_id.say(&ctx.http, "Must provide a path to a video or audio").await);
+            check_msg(msg.channel_id.say(&ctx.http, "Must provide the path of a sound").await);
 
             return Ok(());
         },
     };
 
     let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
+    match play_sound(ctx, guild.id, &path).await {
+        Ok(reply) | Err(reply) => check_msg(msg.channel_id.say(&ctx.http, reply).await),
+    }
+
+    Ok(())
+}
 
+/// Plays an audio file of the sound directory alongside the current track in the voice channel
+/// the bot is connected to in the guild. Returns the reply for the user, as error if nothing plays.
+pub async fn play_sound(ctx: &Context, guild_id: GuildId, path: &str) -> Result<&'static str, &'static str> {
     let manager = songbird::get(ctx).await
         .expect("Songbird Voice client placed in at initialisation.").clone();
 
+    let path = {
+        let data = ctx.data.read().await;
+        match data.get::<BotConfig>() {
+            Some(bot_config) => bot_config.read().await.sound_path(path),
+            None => return Err("There was a problem getting the bot config!"),
+        }
+    };
+    let path = match path {
+        Ok(path) => path,
+        Err(_) => return Err("Sounds must be given relative to the sound directory"),
+    };
+
     if let Some(handler_lock) = manager.get(guild_id) {
         let mut handler = handler_lock.lock().await;
 
-        let source = match songbird::ffmpeg(&path).await {
+        let source = match songbird::ffmpeg(path).await {
             Ok(source) => source,
             Err(why) => {
-                println!("Err starting source: {:?}", why);
-
-                check_msg(msg.channel_id.say(&ctx.http, "Error sourcing ffmpeg").await);
+                warn!("Err starting source: {:?}", why);
 
-                return Ok(());
+                return Err("Error sourcing ffmpeg");
             },
         };
         handler.play_source(source);
 
-        check_msg(msg.channel_id.say(&ctx.http, "Playing song").await);
+        Ok("Playing song")
     } else {
-        check_msg(msg.channel_id.say(&ctx.http, "Not in a voice channel to play in").await);
+        Err("Not in a voice channel to play in")
     }
-
-    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,345 @@
+use std::borrow::Cow;
+use std::time::Duration;
+use serenity::client::Context;
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::framework::standard::macros::{command, group};
+use serenity::model::channel::AttachmentType::Bytes;
+use serenity::model::channel::Message;
+use tracing::warn;
+
+use crate::audit_utils::{audit, AuditEntry};
+use crate::bot_utils::*;
+use crate::commands::audio::Player;
+
+#[group]
+#[prefixes("config")]
+#[only_in(guilds)]
+#[summary = "Inspect and edit the server config"]
+#[default_command(show)]
+#[commands(show, set, reset, export, import)]
+#[checks(command_access)]
+pub struct Config;
+
+#[command]
+#[description("Shows the config of the given server")]
+#[checks(verify_admin)]
+pub async fn show(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = match msg.guild_id {
+        Some(guild) => guild,
+        None => return Ok(()),
+    };
+
+    let (server, prefix, modes) = {
+        let data = ctx.data.read().await;
+
+        let bot_config = match data.get::<BotConfig>() {
+            Some(v) => v,
+            None => {
+                msg.reply(ctx, "There was a problem getting the bot config!").await.unwrap();
+                return Ok(());
+            },
+        };
+        let bot_config = bot_config.read().await;
+
+        match bot_config.server_config(guild_id) {
+            Some((server, _)) => (server.clone(), bot_config.get_guild_prefix(Some(guild_id)), bot_config.get_guild_modes(guild_id)),
+            None => {
+                check_msg(msg.channel_id.say(&ctx.http, "Server config doesnt exist!").await);
+                return Ok(());
+            },
+        }
+    };
+
+    let mut permissions: Vec<String> = server.entity_permissions()
+                                             .iter()
+                                             .map(|(entity, perm)| match server.temporary_grants().get(entity) {
+                                                 Some(grant) => format!("{} {:?} until <t:{}:f>", entity.mention(), perm, grant.expires),
+                                                 None => format!("{} {:?}", entity.mention(), perm),
+                                             })
+                                             .collect();
+    permissions.sort();
+    let mut native_permissions: Vec<String> = server.native_permissions()
+                                                    .iter()
+                                                    .map(|(native, perm)| format!("{:?}: {:?}", native, perm))
+                                                    .collect();
+    native_permissions.sort();
+    let native_permissions = if native_permissions.is_empty() {
+        "off".to_string()
+    } else {
+        native_permissions.join("\n")
+    };
+    let permissions = if permissions.is_empty() {
+        "none".to_string()
+    } else {
+        truncate_lines(&permissions.join("\n"), 1024)
+    };
+
+    check_msg(msg.channel_id.send_message(&ctx.http, |m| {
+        m.embed(|e| {
+            e.title("Server config");
+            e.field("Volume", server.volume(), true);
+            e.field("Auto playlist", server.auto_playlist(), true);
+            e.field("User default", server.user_default(), true);
+            e.field("Prefix", format!("`{}`", prefix), true);
+            e.field("Modules", format!("{:?}", modes), true);
+            e.field("Discord permissions", native_permissions, true);
+            e.field("Log channel", server.log_channel().map(|channel| format!("<#{}>", channel)).unwrap_or_else(|| "none".to_string()), true);
+            e.field("Permissions", permissions, false);
+            e
+        })
+    }).await);
+
+    Ok(())
+}
+
+#[command]
+#[description("Sets a setting of the given server")]
+#[usage("<volume|auto_playlist|user_default|prefix|modules|log_channel|native_permissions> <value> where native_permissions is on, off or e.g. `administrator=admin manage_messages=moderator`")]
+#[checks(verify_admin)]
+pub async fn set(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild = match msg.guild_id {
+        Some(guild) => guild,
+        None => return Ok(()),
+    };
+    let (key, value) = args.rest().split_once(char::is_whitespace).unwrap_or((args.rest(), ""));
+    let key = key.trim_end_matches(',').to_string();
+    let value = value.trim().to_string();
+    if key.is_empty() {
+        check_msg(msg.channel_id.say(&ctx.http, format!("No setting provided, available are {}!", SERVER_SETTINGS.join(", "))).await);
+        return Ok(());
+    }
+    if value.is_empty() {
+        check_msg(msg.channel_id.say(&ctx.http, "No value provided!").await);
+        return Ok(());
+    }
+    let caller = user_permission(ctx, msg, msg.author.id).await.unwrap_or(BotPermission::None);
+
+    let data = ctx.data.read().await;
+
+    let bot_config = match data.get::<BotConfig>() {
+        Some(v) => v,
+        None => {
+            msg.reply(ctx, "There was a problem getting the bot config!").await.unwrap();
+            return Ok(());
+        },
+    };
+    let mut bot_config = bot_config.write().await;
+
+    if key == "native_permissions" {
+        if let Some((server, _)) = bot_config.server_config(guild) {
+            if let Err(why) = server.may_set_native_permissions(caller, &value) {
+                check_msg(msg.channel_id.say(&ctx.http, why).await);
+                return Ok(());
+            }
+        }
+    }
+
+    let old = bot_config.get_guild_setting(guild, &key).unwrap_or_default();
+    if let Err(why) = bot_config.set_guild_setting(guild, &key, &value) {
+        check_msg(msg.channel_id.say(&ctx.http, why).await);
+        return Ok(());
+    }
+    if key == "volume" {
+        let volume = bot_config.get_guild_volume(guild);
+        if let Some(track_handler) = data.get::<Player>().and_then(|players| players.get(&guild)) {
+            if let Err(why) = track_handler.set_volume((volume as f32)/100f32) {
+                warn!("Can not set volume in {}: {:?}", guild, why);
+            }
+        }
+    }
+
+    if check_write(ctx, msg, write_guild_config(&bot_config, guild)).await {
+        let new = bot_config.get_guild_setting(guild, &key).unwrap_or_default();
+        audit(ctx, bot_config.get_guild_log_channel(guild), AuditEntry::new(guild, msg.author.id, key.as_str(), old, new)).await;
+        check_msg(msg.channel_id.say(&ctx.http, format!("Set `{}` to `{}`", key, value)).await);
+    }
+
+    Ok(())
+}
+
+#[command]
+#[description("Restores the default config of the given server, keeping permissions that rank at or above yours")]
+#[checks(verify_admin)]
+pub async fn reset(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild = match msg.guild_id {
+        Some(guild) => guild,
+        None => return Ok(()),
+    };
+    let caller = user_permission(ctx, msg, msg.author.id).await.unwrap_or(BotPermission::None);
+
+    let data = ctx.data.read().await;
+
+    let bot_config = match data.get::<BotConfig>() {
+        Some(v) => v,
+        None => {
+            msg.reply(ctx, "There was a problem getting the bot config!").await.unwrap();
+            return Ok(());
+        },
+    };
+    let mut bot_config = bot_config.write().await;
+
+    let changes = match bot_config.server_config(guild) {
+        Some((server, _)) => yaml_diff(server, &server.reset(caller)).len(),
+        None => 0,
+    };
+    bot_config.reset_guild(guild, caller);
+    if check_write(ctx, msg, write_guild_config(&bot_config, guild)).await {
+        let entry = AuditEntry::new(guild, msg.author.id, "config", format!("{} custom values", changes), "defaults");
+        audit(ctx, bot_config.get_guild_log_channel(guild), entry).await;
+        check_msg(msg.channel_id.say(&ctx.http, "Server config reset to defaults").await);
+    }
+
+    Ok(())
+}
+
+#[command]
+#[description("Exports the config of the given server as a file")]
+#[checks(verify_admin)]
+pub async fn export(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild = match msg.guild_id {
+        Some(guild) => guild,
+        None => return Ok(()),
+    };
+
+    let export = {
+        let data = ctx.data.read().await;
+
+        let bot_config = match data.get::<BotConfig>() {
+            Some(v) => v,
+            None => {
+                msg.reply(ctx, "There was a problem getting the bot config!").await.unwrap();
+                return Ok(());
+            },
+        };
+        let bot_config = bot_config.read().await;
+
+        match bot_config.server_config(guild) {
+            Some((server, _)) => export_server_config(server)?,
+            None => {
+                check_msg(msg.channel_id.say(&ctx.http, "Server config doesnt exist!").await);
+                return Ok(());
+            },
+        }
+    };
+
+    check_msg(msg.channel_id.send_message(&ctx.http, |m| {
+        m.add_file(Bytes {
+            data: Cow::from(export.as_bytes()),
+            filename: format!("server_config_{}.yml", guild),
+        });
+        m
+    }).await);
+
+    Ok(())
+}
+
+#[command]
+#[description("Imports a server config exported with `config export`, the changes are shown for confirmation first")]
+#[usage("Attach the exported file to the message")]
+#[checks(verify_admin)]
+pub async fn import(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild = match msg.guild_id {
+        Some(guild) => guild,
+        None => return Ok(()),
+    };
+    let attachment = match msg.attachments.first() {
+        Some(attachment) if attachment.size <= 1_000_000 => attachment,
+        Some(_) => {
+            check_msg(msg.channel_id.say(&ctx.http, "Attached file is too large!").await);
+            return Ok(());
+        },
+        None => {
+            check_msg(msg.channel_id.say(&ctx.http, "No file attached!").await);
+            return Ok(());
+        },
+    };
+    let mut new_server = match import_server_config(&attachment.download().await?) {
+        Ok(server) => server,
+        Err(why) => {
+            check_msg(msg.channel_id.say(&ctx.http, why).await);
+            return Ok(());
+        },
+    };
+    // older exports can still contain untyped entity ids
+    if let Some(guild) = guild.to_guild_cached(ctx) {
+        new_server.resolve_legacy_entities(&guild);
+    }
+    let caller = user_permission(ctx, msg, msg.author.id).await.unwrap_or(BotPermission::None);
+
+    let changes = {
+        let data = ctx.data.read().await;
+
+        let bot_config = match data.get::<BotConfig>() {
+            Some(v) => v,
+            None => {
+                msg.reply(ctx, "There was a problem getting the bot config!").await.unwrap();
+                return Ok(());
+            },
+        };
+        let bot_config = bot_config.read().await;
+
+        match bot_config.server_config(guild) {
+            Some((server, _)) => {
+                if let Err(why) = server.may_replace_permissions(caller, &new_server) {
+                    check_msg(msg.channel_id.say(&ctx.http, why).await);
+                    return Ok(());
+                }
+                yaml_diff(server, &new_server)
+            },
+            None => {
+                check_msg(msg.channel_id.say(&ctx.http, "Server config doesnt exist!").await);
+                return Ok(());
+            },
+        }
+    };
+    if changes.is_empty() {
+        check_msg(msg.channel_id.say(&ctx.http, "Imported config matches the current one.").await);
+        return Ok(());
+    }
+
+    let preview = truncate_lines(&changes.join("\n"), 1800);
+    let preview = msg.channel_id.say(&ctx.http, format!("Changes:\n```\n{}\n```React with ✅ within 60 seconds to apply them.", preview)).await?;
+    preview.react(ctx, '✅').await?;
+    preview.react(ctx, '❌').await?;
+
+    let confirmed = match preview.await_reaction(ctx).author_id(msg.author.id).timeout(Duration::from_secs(60)).await {
+        Some(action) => action.as_inner_ref().emoji.unicode_eq("✅"),
+        None => false,
+    };
+    if !confirmed {
+        check_msg(msg.channel_id.say(&ctx.http, "Import cancelled").await);
+        return Ok(());
+    }
+
+    let data = ctx.data.read().await;
+
+    let bot_config = match data.get::<BotConfig>() {
+        Some(v) => v,
+        None => {
+            msg.reply(ctx, "There was a problem getting the bot config!").await.unwrap();
+            return Ok(());
+        },
+    };
+    let mut bot_config = bot_config.write().await;
+
+    // the grants could have changed while waiting for the confirmation
+    if let Some(Err(why)) = bot_config.server_config(guild).map(|(server, _)| server.may_replace_permissions(caller, &new_server)) {
+        check_msg(msg.channel_id.say(&ctx.http, why).await);
+        return Ok(());
+    }
+    bot_config.replace_guild(guild, new_server);
+    let volume = bot_config.get_guild_volume(guild);
+    if let Some(track_handler) = data.get::<Player>().and_then(|players| players.get(&guild)) {
+        if let Err(why) = track_handler.set_volume((volume as f32)/100f32) {
+            warn!("Can not set volume in {}: {:?}", guild, why);
+        }
+    }
+
+    if check_write(ctx, msg, write_guild_config(&bot_config, guild)).await {
+        let entry = AuditEntry::new(guild, msg.author.id, "config", "previous", format!("imported with {} changes", changes.len()));
+        audit(ctx, bot_config.get_guild_log_channel(guild), entry).await;
+        check_msg(msg.channel_id.say(&ctx.http, "Server config imported").await);
+    }
+
+    Ok(())
+}
@@ -1,17 +1,20 @@
 use std::sync::Arc;
+use serenity::builder::CreateEmbed;
 use serenity::client::bridge::gateway::{ShardId, ShardManager};
 use serenity::client::Context;
 use serenity::framework::standard::{Args, CommandResult};
 use serenity::framework::standard::macros::{command, group};
 use serenity::model::channel::Message;
-use serenity::model::id::UserId;
+use serenity::model::id::{ChannelId, GuildId, UserId};
 use serenity::prelude::TypeMapKey;
 use tokio::sync::Mutex;
 
 use crate::bot_utils::*;
+use crate::stats_utils::{BotStats, MAX_STATS_DAYS};
 
 #[group]
-#[commands(latency,whoami,whois)]
+#[commands(latency,whoami,whois,stats)]
+#[checks(command_access)]
 pub struct General;
 
 pub struct ShardManagerContainer;
@@ -57,6 +60,8 @@ pub async fn whoami(ctx: &Context, msg: &Message) -> CommandResult {
 }
 
 #[command]
+#[description("Shows the permission of a user in this channel and where it comes from")]
+#[usage("The sources are the bot owner, the server user default, the user, their roles, this channel and the voice channel shared with the bot; the highest one wins.")]
 #[checks(verify_user)]
 pub async fn whois(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
     let choosen_id = match args.single::<UserId>() {
@@ -66,6 +71,107 @@ pub async fn whois(ctx: &Context, msg: &Message, mut args: Args) -> CommandResul
             return Ok(());
         },
     };
-    msg.reply(ctx, &format!("Given id is {:?}", user_permission(ctx, msg, choosen_id).await)).await.expect("User has no permission?");
+    match permission_embed(ctx, msg.guild_id, msg.channel_id, choosen_id).await {
+        Ok(embed) => {
+            msg.channel_id.send_message(&ctx.http, |m| m.set_embed(embed)).await?;
+        },
+        Err(why) => check_msg(msg.channel_id.say(&ctx.http, why).await),
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Builds an embed of the permission of a user in a channel and the sources granting it.
+pub async fn permission_embed(ctx: &Context, guild: Option<GuildId>, channel: ChannelId, user: UserId) -> Result<CreateEmbed, String> {
+    let explanation = explain_permission_in(ctx, guild, channel, user).await
+                                                                      .map_err(|why| format!("Could not resolve the permission: {:?}", why))?;
+
+    let sources: Vec<String> = explanation.grants()
+                                          .map(|(source, perm)| format!("{:?} from {}", perm, source))
+                                          .collect();
+    let sources = if sources.is_empty() {
+        "no permission assigned".to_string()
+    } else {
+        sources.join("\n")
+    };
+    let mut embed = CreateEmbed::default();
+    embed.title("Permission");
+    embed.description(format!("<@{}> is {:?} in <#{}>", user, explanation.permission, channel));
+    embed.field("Sources", truncate_lines(&sources, 1024), false);
+    Ok(embed)
+}
+
+#[command]
+#[description("Shows the most used commands, their error rates and the busiest hours")]
+#[usage("[days] [global] where days defaults to 30 and goes up to 365 and global, covering all servers, is limited to owners")]
+#[checks(verify_user)]
+pub async fn stats(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let mut days = 30;
+    let mut global = false;
+    for arg in args.rest().split(|c: char| c == ',' || c.is_whitespace()).filter(|arg| !arg.is_empty()) {
+        match arg {
+            "global" => global = true,
+            _ => match arg.parse::<u64>() {
+                Ok(value) if value > 0 && value <= MAX_STATS_DAYS => days = value,
+                _ => {
+                    check_msg(msg.channel_id.say(&ctx.http, format!("`{}` is neither a number of days up to {} nor `global`!", arg, MAX_STATS_DAYS)).await);
+                    return Ok(());
+                },
+            },
+        }
+    }
+
+    let summary = {
+        let data = ctx.data.read().await;
+
+        if global {
+            let is_owner = match data.get::<BotConfig>() {
+                Some(bot_config) => bot_config.read().await.is_owner(msg.author.id),
+                None => false,
+            };
+            if !is_owner {
+                check_msg(msg.channel_id.say(&ctx.http, "Only owners can see global stats!").await);
+                return Ok(());
+            }
+        } else if msg.guild_id.is_none() {
+            check_msg(msg.channel_id.say(&ctx.http, "Use `global` outside of servers!").await);
+            return Ok(());
+        }
+
+        match data.get::<BotStats>() {
+            Some(stats) => stats.read().await.summary(if global { None } else { msg.guild_id }, days),
+            None => {
+                msg.reply(ctx, "There was a problem getting the stats!").await?;
+                return Ok(());
+            },
+        }
+    };
+
+    if summary.total.invocations == 0 {
+        check_msg(msg.channel_id.say(&ctx.http, format!("No commands were used in the last {} days.", days)).await);
+        return Ok(());
+    }
+
+    let top_commands: Vec<String> = summary.commands
+                                           .iter()
+                                           .take(10)
+                                           .map(|(command, usage)| format!("`{}`: {} uses, {:.1}% errors, {} ms avg",
+                                                                           command, usage.invocations, usage.error_rate() * 100.0, usage.average_millis()))
+                                           .collect();
+    let busiest_hours: Vec<String> = summary.busiest_hours()
+                                            .iter()
+                                            .take(3)
+                                            .map(|(hour, count)| format!("{:02}:00-{:02}:59 UTC: {} uses", hour, hour, count))
+                                            .collect();
+    let scope = if global { "all servers" } else { "this server" };
+
+    msg.channel_id.send_message(&ctx.http, |m| {
+        m.embed(|e| {
+            e.title(format!("Command stats of {} in the last {} days", scope, days));
+            e.description(format!("{} uses, {:.1}% errors", summary.total.invocations, summary.total.error_rate() * 100.0));
+            e.field("Top commands", truncate_lines(&top_commands.join("\n"), 1024), false);
+            e.field("Busiest hours", busiest_hours.join("\n"), false);
+            e
+        })
+    }).await?;
+    Ok(())
+}
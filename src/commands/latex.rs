@@ -5,17 +5,36 @@ use serenity::framework::standard::macros::{command, group};
 use serenity::model::channel::AttachmentType::Bytes;
 use serenity::model::channel::Message;
 
+use crate::bot_utils::*;
 use crate::latex_utils;
+use crate::metrics_utils::metrics;
 
 #[group]
 //#[summary = "Latex commands"]
 #[commands(math, tex)]
+#[checks(command_access, latex_module)]
 pub struct Latex;
 
-async fn latex_handling(ctx: &Context, msg: &Message, tex_string: String){
-    if let Ok(image) = tokio::task::spawn_blocking(move || {
+/// Wraps a formula into a displaystyle math environment.
+pub fn math_source(formula: &str) -> String {
+    "$\\displaystyle\n".to_owned() + formula + "$"
+}
+
+/// Renders a LaTeX document to a png, `None` if it is not valid LaTeX.
+pub async fn render_latex(tex_string: String) -> Option<Vec<u8>> {
+    let timer = metrics().latex_render_seconds.start_timer();
+    let image = tokio::task::spawn_blocking(move || {
             latex_utils::latex_tex_png(&tex_string)
-    }).await.expect("LaTeX future didn't hold!"){
+    }).await.expect("LaTeX future didn't hold!").ok();
+    timer.observe_duration();
+    if image.is_none() {
+        metrics().latex_failures.inc();
+    }
+    image
+}
+
+async fn latex_handling(ctx: &Context, msg: &Message, tex_string: String){
+    if let Some(image) = render_latex(tex_string).await {
         msg.channel_id.send_message(&ctx,|m| {
             // Reply to the given message
             //m.reference_message(&msg);
@@ -36,7 +55,7 @@ async fn latex_handling(ctx: &Context, msg: &Message, tex_string: String){
 pub async fn math(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
     match args.single_quoted::<String>() {
         Ok(message_string) => {
-            latex_handling(ctx, msg,("$\\displaystyle\n".to_owned() + &message_string + "$").to_string()).await;
+            latex_handling(ctx, msg, math_source(&message_string)).await;
         },
         Err(_) => {
             msg.reply(ctx, "An argument is required to run this command.").await?;
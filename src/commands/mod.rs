@@ -1,5 +1,86 @@
+use serenity::framework::standard::{Command, CommandGroup, CommandOptions};
+
 pub mod audio;
+pub mod config;
 pub mod general;
 pub mod latex;
 pub mod moderation;
 pub mod owner;
+pub mod slash;
+
+/// All command groups, in the order they are registered with the framework.
+pub static GROUPS: &[&CommandGroup] = &[
+    &general::GENERAL_GROUP,
+    &latex::LATEX_GROUP,
+    &audio::AUDIO_GROUP,
+    &audio::music::MUSIC_GROUP,
+    &moderation::MODERATION_GROUP,
+    &moderation::command_permission::COMMANDPERMISSION_GROUP,
+    &moderation::perms::PERMS_GROUP,
+    &config::CONFIG_GROUP,
+    &audio::soundboard::SOUNDBOARD_GROUP,
+    &owner::OWNER_GROUP,
+];
+
+fn collect_groups(groups: &[&'static CommandGroup], out: &mut Vec<&'static CommandGroup>) {
+    for group in groups {
+        out.push(*group);
+        collect_groups(group.options.sub_groups, out);
+    }
+}
+
+fn collect_commands(commands: &[&'static Command], out: &mut Vec<&'static Command>) {
+    for command in commands {
+        out.push(*command);
+        collect_commands(command.options.sub_commands, out);
+    }
+}
+
+/// Lists every group including sub groups.
+pub fn all_groups() -> Vec<&'static CommandGroup> {
+    let mut groups = Vec::new();
+    collect_groups(GROUPS, &mut groups);
+    groups
+}
+
+/// Lists the commands of a group including sub commands, commands of sub groups are not included.
+pub fn group_commands(group: &'static CommandGroup) -> Vec<&'static Command> {
+    let mut commands = Vec::new();
+    collect_commands(group.options.commands, &mut commands);
+    commands
+}
+
+/// Looks up a group by its name, ignoring case.
+pub fn find_group(name: &str) -> Option<&'static CommandGroup> {
+    all_groups().into_iter().find(|group| group.name.eq_ignore_ascii_case(name))
+}
+
+/// Returns the name a command is addressed by in permission overrides, commands of groups with
+/// a prefix are qualified by it, e.g. `config set`.
+pub fn command_key(group: Option<&CommandGroup>, options: &CommandOptions) -> String {
+    let name = options.names.first().copied().unwrap_or_default();
+    match group.and_then(|group| group.options.prefixes.first()) {
+        Some(prefix) => format!("{} {}", prefix, name),
+        None => name.to_string(),
+    }
+}
+
+/// Looks up a command by any of its names together with the group it belongs to,
+/// commands of groups with a prefix have to be qualified by it.
+pub fn find_command(name: &str) -> Option<(&'static CommandGroup, &'static Command)> {
+    let matches = |group: &CommandGroup, command: &Command| {
+        command.options.names.iter().any(|alias| match group.options.prefixes {
+            [] => alias.eq_ignore_ascii_case(name),
+            prefixes => prefixes.iter().any(|prefix| format!("{} {}", prefix, alias).eq_ignore_ascii_case(name)),
+        })
+    };
+    all_groups().into_iter()
+                .flat_map(|group| group_commands(group).into_iter().map(move |command| (group, command)))
+                .find(|(group, command)| matches(group, command))
+}
+
+/// Finds the group of the command with the given options, as passed to checks.
+pub fn command_group(options: &CommandOptions) -> Option<&'static CommandGroup> {
+    all_groups().into_iter()
+                .find(|group| group_commands(group).iter().any(|command| std::ptr::eq(command.options, options)))
+}
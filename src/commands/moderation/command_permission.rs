@@ -0,0 +1,348 @@
+use std::collections::HashSet;
+use serenity::client::Context;
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::framework::standard::macros::{command, group};
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId};
+
+use crate::audit_utils::{audit, AuditEntry};
+use crate::bot_utils::*;
+use crate::commands::{command_key, find_command, find_group};
+
+#[group]
+#[prefixes("command_perm")]
+#[only_in(guilds)]
+#[summary = "Override the permission and channels of commands on this server"]
+#[default_command(list)]
+#[commands(set, clear, allow, deny, unrestrict, list)]
+#[checks(command_access)]
+pub struct CommandPermission;
+
+/// Resolves a command or group name to its override key and compiled-in permission, which is
+/// `None` for targets without a permission check.
+fn resolve_target(name: &str) -> Result<(String, BotPermission), String> {
+    if let Some((group, command)) = find_command(name) {
+        let perm = default_command_permission(group, command.options).unwrap_or(BotPermission::None);
+        return Ok((command_key(Some(group), command.options), perm));
+    }
+    if let Some(group) = find_group(name) {
+        // A group override is limited by the most privileged command of the group.
+        let perm = default_group_permission(group).unwrap_or(BotPermission::None);
+        return Ok((group.name.to_lowercase(), perm));
+    }
+    Err(format!("Unknown command or group `{}`!", name))
+}
+
+/// Describes the permission override of a command or group for the audit log.
+fn permission_override(bot_config: &ConfigStruct, guild: GuildId, key: &str) -> String {
+    bot_config.server_config(guild)
+              .and_then(|(server, _)| server.command_permissions().get(key).map(|perm| format!("{:?}", perm)))
+              .unwrap_or_else(|| "default".to_string())
+}
+
+/// Describes the channel restrictions of a command or group for the audit log.
+fn channel_restriction(bot_config: &ConfigStruct, guild: GuildId, key: &str) -> String {
+    bot_config.server_config(guild)
+              .and_then(|(server, _)| server.command_channels().get(key))
+              .map(|restriction| format!("allowed: {} denied: {}", channel_list(&restriction.allow), channel_list(&restriction.deny)))
+              .unwrap_or_else(|| "unrestricted".to_string())
+}
+
+#[command]
+#[description("Overrides the permission a command or a whole group requires on the given server")]
+#[usage("<command|group>, <owner|admin|moderator|user|none> where commands of prefixed groups include the prefix, e.g. `config set`")]
+#[checks(verify_admin)]
+pub async fn set(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild = match msg.guild_id {
+        Some(guild) => guild,
+        None => return Ok(()),
+    };
+    let (name, perm) = match (args.single::<String>(), args.single::<String>()) {
+        (Ok(name), Ok(perm)) => (name, perm),
+        _ => {
+            check_msg(msg.channel_id.say(&ctx.http, "Usage: `command_perm set <command|group>, <permission>`").await);
+            return Ok(());
+        },
+    };
+    let perm = match perm.parse::<BotPermission>() {
+        Ok(perm) => perm,
+        Err(why) => {
+            check_msg(msg.channel_id.say(&ctx.http, why).await);
+            return Ok(());
+        },
+    };
+    let (key, default_perm) = match resolve_target(&name) {
+        Ok(target) => target,
+        Err(why) => {
+            check_msg(msg.channel_id.say(&ctx.http, why).await);
+            return Ok(());
+        },
+    };
+
+    // Nobody may change a command they could not run by default or grant more than they have.
+    let author_perm = user_permission(ctx, msg, msg.author.id).await.unwrap_or(BotPermission::None);
+    if !author_perm.dominates(&default_perm) || !author_perm.dominates(&perm) {
+        check_msg(msg.channel_id.say(&ctx.http, "Insufficient Permission!").await);
+        return Ok(());
+    }
+
+    let data = ctx.data.read().await;
+
+    let bot_config = match data.get::<BotConfig>() {
+        Some(v) => v,
+        None => {
+            msg.reply(ctx, "There was a problem getting the bot config!").await.unwrap();
+            return Ok(());
+        },
+    };
+    let mut bot_config = bot_config.write().await;
+
+    let old = permission_override(&bot_config, guild, &key);
+    bot_config.set_command_permission(guild, &key, Some(perm));
+    if check_write(ctx, msg, write_guild_config(&bot_config, guild)).await {
+        let entry = AuditEntry::new(guild, msg.author.id, format!("command_perm {}", key), old, format!("{:?}", perm));
+        audit(ctx, bot_config.get_guild_log_channel(guild), entry).await;
+        check_msg(msg.channel_id.say(&ctx.http, format!("`{}` now requires {:?} (default {:?})", key, perm, default_perm)).await);
+    }
+
+    Ok(())
+}
+
+#[command]
+#[description("Removes the permission override of a command or group")]
+#[usage("<command|group>")]
+#[checks(verify_admin)]
+pub async fn clear(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild = match msg.guild_id {
+        Some(guild) => guild,
+        None => return Ok(()),
+    };
+    let name = match args.single::<String>() {
+        Ok(name) => name,
+        Err(_) => {
+            check_msg(msg.channel_id.say(&ctx.http, "No command or group provided!").await);
+            return Ok(());
+        },
+    };
+    let (key, default_perm) = match resolve_target(&name) {
+        Ok(target) => target,
+        Err(why) => {
+            check_msg(msg.channel_id.say(&ctx.http, why).await);
+            return Ok(());
+        },
+    };
+
+    let author_perm = user_permission(ctx, msg, msg.author.id).await.unwrap_or(BotPermission::None);
+    if !author_perm.dominates(&default_perm) {
+        check_msg(msg.channel_id.say(&ctx.http, "Insufficient Permission!").await);
+        return Ok(());
+    }
+
+    let data = ctx.data.read().await;
+
+    let bot_config = match data.get::<BotConfig>() {
+        Some(v) => v,
+        None => {
+            msg.reply(ctx, "There was a problem getting the bot config!").await.unwrap();
+            return Ok(());
+        },
+    };
+    let mut bot_config = bot_config.write().await;
+
+    let old = permission_override(&bot_config, guild, &key);
+    bot_config.set_command_permission(guild, &key, None);
+    if check_write(ctx, msg, write_guild_config(&bot_config, guild)).await {
+        let entry = AuditEntry::new(guild, msg.author.id, format!("command_perm {}", key), old, "default");
+        audit(ctx, bot_config.get_guild_log_channel(guild), entry).await;
+        check_msg(msg.channel_id.say(&ctx.http, format!("`{}` requires {:?} again", key, default_perm)).await);
+    }
+
+    Ok(())
+}
+
+async fn restrict_channel(ctx: &Context, msg: &Message, mut args: Args, allow: bool) -> CommandResult {
+    let guild = match msg.guild_id {
+        Some(guild) => guild,
+        None => return Ok(()),
+    };
+    let (name, channel) = match (args.single::<String>(), args.single::<ChannelId>()) {
+        (Ok(name), Ok(channel)) => (name, channel),
+        _ => {
+            check_msg(msg.channel_id.say(&ctx.http, "Usage: `<command|group>, <channel>`").await);
+            return Ok(());
+        },
+    };
+    let (key, default_perm) = match resolve_target(&name) {
+        Ok(target) => target,
+        Err(why) => {
+            check_msg(msg.channel_id.say(&ctx.http, why).await);
+            return Ok(());
+        },
+    };
+
+    let author_perm = user_permission(ctx, msg, msg.author.id).await.unwrap_or(BotPermission::None);
+    if !author_perm.dominates(&default_perm) {
+        check_msg(msg.channel_id.say(&ctx.http, "Insufficient Permission!").await);
+        return Ok(());
+    }
+
+    let data = ctx.data.read().await;
+
+    let bot_config = match data.get::<BotConfig>() {
+        Some(v) => v,
+        None => {
+            msg.reply(ctx, "There was a problem getting the bot config!").await.unwrap();
+            return Ok(());
+        },
+    };
+    let mut bot_config = bot_config.write().await;
+
+    let old = channel_restriction(&bot_config, guild, &key);
+    bot_config.restrict_command_channel(guild, &key, channel, allow);
+    if check_write(ctx, msg, write_guild_config(&bot_config, guild)).await {
+        let entry = AuditEntry::new(guild, msg.author.id, format!("command_channels {}", key), old, channel_restriction(&bot_config, guild, &key));
+        audit(ctx, bot_config.get_guild_log_channel(guild), entry).await;
+        let verb = if allow { "allowed" } else { "denied" };
+        check_msg(msg.channel_id.say(&ctx.http, format!("`{}` is {} in <#{}>", key, verb, channel)).await);
+    }
+
+    Ok(())
+}
+
+#[command]
+#[description("Allows a command or group in a text or voice channel, once a channel is allowed all others are denied")]
+#[usage("<command|group>, <channel>")]
+#[checks(verify_admin)]
+pub async fn allow(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    restrict_channel(ctx, msg, args, true).await
+}
+
+#[command]
+#[description("Denies a command or group in a text or voice channel")]
+#[usage("<command|group>, <channel>")]
+#[checks(verify_admin)]
+pub async fn deny(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    restrict_channel(ctx, msg, args, false).await
+}
+
+#[command]
+#[description("Removes all channel restrictions of a command or group")]
+#[usage("<command|group>")]
+#[checks(verify_admin)]
+pub async fn unrestrict(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild = match msg.guild_id {
+        Some(guild) => guild,
+        None => return Ok(()),
+    };
+    let name = match args.single::<String>() {
+        Ok(name) => name,
+        Err(_) => {
+            check_msg(msg.channel_id.say(&ctx.http, "No command or group provided!").await);
+            return Ok(());
+        },
+    };
+    let (key, default_perm) = match resolve_target(&name) {
+        Ok(target) => target,
+        Err(why) => {
+            check_msg(msg.channel_id.say(&ctx.http, why).await);
+            return Ok(());
+        },
+    };
+
+    let author_perm = user_permission(ctx, msg, msg.author.id).await.unwrap_or(BotPermission::None);
+    if !author_perm.dominates(&default_perm) {
+        check_msg(msg.channel_id.say(&ctx.http, "Insufficient Permission!").await);
+        return Ok(());
+    }
+
+    let data = ctx.data.read().await;
+
+    let bot_config = match data.get::<BotConfig>() {
+        Some(v) => v,
+        None => {
+            msg.reply(ctx, "There was a problem getting the bot config!").await.unwrap();
+            return Ok(());
+        },
+    };
+    let mut bot_config = bot_config.write().await;
+
+    let old = channel_restriction(&bot_config, guild, &key);
+    bot_config.clear_command_channels(guild, &key);
+    if check_write(ctx, msg, write_guild_config(&bot_config, guild)).await {
+        let entry = AuditEntry::new(guild, msg.author.id, format!("command_channels {}", key), old, "unrestricted");
+        audit(ctx, bot_config.get_guild_log_channel(guild), entry).await;
+        check_msg(msg.channel_id.say(&ctx.http, format!("`{}` is available in all channels again", key)).await);
+    }
+
+    Ok(())
+}
+
+fn channel_list(channels: &HashSet<ChannelId>) -> String {
+    let mut channels: Vec<String> = channels.iter().map(|channel| format!("<#{}>", channel)).collect();
+    channels.sort();
+    channels.join(" ")
+}
+
+#[command]
+#[description("Lists the permission overrides and channel restrictions of the given server")]
+#[checks(verify_admin)]
+pub async fn list(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild = match msg.guild_id {
+        Some(guild) => guild,
+        None => return Ok(()),
+    };
+
+    let (mut overrides, mut restrictions): (Vec<String>, Vec<String>) = {
+        let data = ctx.data.read().await;
+
+        let bot_config = match data.get::<BotConfig>() {
+            Some(v) => v,
+            None => {
+                msg.reply(ctx, "There was a problem getting the bot config!").await.unwrap();
+                return Ok(());
+            },
+        };
+        let bot_config = bot_config.read().await;
+
+        match bot_config.server_config(guild) {
+            Some((server, _)) => (server.command_permissions()
+                                        .iter()
+                                        .map(|(name, perm)| match resolve_target(name) {
+                                            Ok((_, default_perm)) => format!("{}: {:?} (default {:?})", name, perm, default_perm),
+                                            Err(_) => format!("{}: {:?} (unknown command)", name, perm),
+                                        })
+                                        .collect(),
+                                  server.command_channels()
+                                        .iter()
+                                        .map(|(name, restriction)| format!("`{}` allowed: {} denied: {}", name, channel_list(&restriction.allow), channel_list(&restriction.deny)))
+                                        .collect()),
+            None => {
+                check_msg(msg.channel_id.say(&ctx.http, "Server config doesnt exist!").await);
+                return Ok(());
+            },
+        }
+    };
+    overrides.sort();
+    restrictions.sort();
+
+    let overrides = if overrides.is_empty() {
+        "none, all commands use their default permission".to_string()
+    } else {
+        format!("```\n{}\n```", truncate_lines(&overrides.join("\n"), 1000))
+    };
+    let restrictions = if restrictions.is_empty() {
+        "none, all commands are available in every channel".to_string()
+    } else {
+        truncate_lines(&restrictions.join("\n"), 1024)
+    };
+    check_msg(msg.channel_id.send_message(&ctx.http, |m| {
+        m.embed(|e| {
+            e.title("Command permissions");
+            e.field("Permission overrides", overrides, false);
+            e.field("Channel restrictions", restrictions, false);
+            e
+        })
+    }).await);
+
+    Ok(())
+}
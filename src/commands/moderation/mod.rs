@@ -1,42 +1,129 @@
+use std::collections::HashSet;
+use std::time::Duration;
 use serenity::client::Context;
 use serenity::framework::standard::{Args, CommandResult};
 use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId, UserId};
 use serenity::framework::standard::macros::{command, group};
+use tracing::error;
+use crate::audit_utils::{audit, read_audit_entries, AuditEntry};
 use crate::bot_utils::*;
+use crate::entity_id::{parse_entity, EntityId};
+
+pub mod command_permission;
+pub mod perms;
 
 #[group]
-#[commands(make_admin,make_moderator,make_user,demote,set_user_default)]
+#[commands(make_admin,make_moderator,make_user,demote,block,unblock,set_user_default,prefix,modules,audit_log)]
+#[checks(command_access)]
 pub struct Moderation;
 
-async fn make_perm(ctx: &Context, msg: &Message, mut args: Args, perm: BotPermission) -> CommandResult {
+async fn make_perm(ctx: &Context, msg: &Message, args: Args, perm: BotPermission) -> CommandResult {
+    let guild = match msg.guild(&ctx.cache) {
+        Some(guild) => guild,
+        None => return Ok(()),
+    };
+    let (input, duration) = split_duration(args.rest());
+    if input.is_empty() {
+        check_msg(msg.channel_id.say(&ctx.http, "No user, role or channel provided!").await);
+        return Ok(());
+    }
+    let choosen_entity = match parse_entity(ctx, &guild, input).await {
+        Ok(entity) => entity,
+        Err(why) => {
+            check_msg(msg.channel_id.say(&ctx.http, why).await);
+            return Ok(());
+        },
+    };
+
+    let reply = match change_permission(ctx, guild.id, msg.channel_id, msg.author.id, choosen_entity, duration, perm).await {
+        Ok(reply) => reply,
+        Err(why) => why,
+    };
+    check_msg(msg.channel_id.send_message(&ctx.http, |m| {
+        m.content(reply);
+        m.allowed_mentions(|am| am.empty_parse())
+    }).await);
+    Ok(())
+}
+
+/// Splits a trailing duration like `3h` off the entity, a last word that is no duration
+/// belongs to the entity so role names can contain spaces.
+fn split_duration(input: &str) -> (&str, Option<Duration>) {
+    let input = input.trim();
+    match input.rsplit_once(|c: char| c == ',' || c.is_whitespace()) {
+        Some((entity, duration)) => match parse_duration(duration) {
+            Ok(duration) => (entity.trim_end_matches(|c: char| c == ',' || c.is_whitespace()), Some(duration)),
+            Err(_) => (input, None),
+        },
+        None => (input, None),
+    }
+}
+
+/// Assigns a permission to an entity of the guild on behalf of the author, for the given
+/// duration if any. Returns the reply for the user, which mentions the entity.
+pub async fn change_permission(ctx: &Context, guild: GuildId, channel: ChannelId, author: UserId, choosen_entity: EntityId,
+                               duration: Option<Duration>, perm: BotPermission) -> Result<String, String> {
+    if duration.is_some() && perm == BotPermission::None {
+        return Err("A demotion cannot be temporary!".to_string());
+    }
+    if perm == BotPermission::Blocked && !matches!(choosen_entity, EntityId::User(_) | EntityId::Role(_)) {
+        return Err("Only users and roles can be blocked!".to_string());
+    }
+
+    // users are ranked by their effective permission, other entities by their own entry
+    let caller_perm = user_permission_in(ctx, Some(guild), channel, author).await.unwrap_or(BotPermission::None);
+    let user_perm = match choosen_entity {
+        EntityId::User(user) => Some(user_permission_in(ctx, Some(guild), channel, user).await.unwrap_or(BotPermission::None)),
+        _ => None,
+    };
+
     let mut data = ctx.data.write().await;
 
     let bot_config = match data.get_mut::<BotConfig>() {
         Some(v) => v,
-        None => {
-            msg.reply(ctx, "There was a problem getting the bot config!").await.unwrap();
-            return Ok(());
-        },
+        None => return Err("There was a problem getting the bot config!".to_string()),
     };
     let mut bot_config = bot_config.write().await;
 
-    let choosen_id = match args.single::<u64>() {
-        Ok(id) => id,
-        Err(_) => {
-            check_msg(msg.channel_id.say(&ctx.http, "No id provided!").await);
-            return Ok(());
-        },
+    // a role can be held by members that rank above the caller, which the member cache can't rule out
+    if perm == BotPermission::Blocked && matches!(choosen_entity, EntityId::Role(_)) && caller_perm != BotPermission::Owner {
+        return Err("Only owners can block roles!".to_string());
+    }
+    let current_perm = user_perm.unwrap_or_else(|| bot_config.get_entity_permission(guild, choosen_entity));
+    caller_perm.may_change(&current_perm, &perm)?;
+
+    let old_perm = bot_config.get_entity_permission(guild, choosen_entity);
+    let was_temporary = bot_config.server_config(guild)
+                                  .map_or(false, |(server, _)| server.temporary_grants().contains_key(&choosen_entity));
+    let expires = duration.map(|duration| unix_time() + duration.as_secs());
+    match expires {
+        Some(expires) => bot_config.grant_temporary(guild, choosen_entity, perm, expires),
+        None => bot_config.insert_entity_guild(guild, choosen_entity, perm),
+    }
+    // the expiry of temporary grants is part of the guild settings
+    let result = if was_temporary || expires.is_some() {
+        write_guild_config(&bot_config, guild)
+    } else {
+        write_entity_permission(&bot_config, guild, choosen_entity)
     };
-    if let Some(guild) = msg.guild_id{
-        bot_config.insert_entity_guild( guild, choosen_id, perm);
+    if let Err(why) = result {
+        error!("Config could not be written: {:?}", why);
+        return Err("Config could not be written!".to_string());
     }
-    write_config(&bot_config).expect("Config could not be written!");
-    Ok(())
+
+    let until = expires.map(|expires| format!(" until <t:{}:f>", expires)).unwrap_or_default();
+    let entry = AuditEntry::new(guild, author, format!("permission {}", choosen_entity),
+                                format!("{:?}", old_perm), format!("{:?}{}", perm, until));
+    audit(ctx, bot_config.get_guild_log_channel(guild), entry).await;
+
+    Ok(format!("{} is now {:?}{}", choosen_entity.mention(), perm, until))
 }
 
 #[command]
 #[only_in(guilds)]
-#[description("Makes given role id an admin on the given server")]
+#[description("Makes given user, role or channel an admin on the given server")]
+#[usage("<user mention|role mention|role name|channel mention|everyone|id> [duration like 3h or 1d12h]")]
 #[checks(verify_owner)]
 pub async fn make_admin(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
     make_perm(ctx, msg, args, BotPermission::Admin).await
@@ -44,7 +131,8 @@ pub async fn make_admin(ctx: &Context, msg: &Message, args: Args) -> CommandResu
 
 #[command]
 #[only_in(guilds)]
-#[description("Makes given role id an moderator on the given server")]
+#[description("Makes given user, role or channel an moderator on the given server")]
+#[usage("<user mention|role mention|role name|channel mention|everyone|id> [duration like 3h or 1d12h]")]
 #[checks(verify_admin)]
 pub async fn make_moderator(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
     make_perm(ctx, msg, args, BotPermission::Moderator).await
@@ -52,7 +140,8 @@ pub async fn make_moderator(ctx: &Context, msg: &Message, args: Args) -> Command
 
 #[command]
 #[only_in(guilds)]
-#[description("Makes given role id an user on the given server")]
+#[description("Makes given user, role or channel an user on the given server, a channel grants it to everyone using it")]
+#[usage("<user mention|role mention|role name|channel mention|everyone|id> [duration like 3h or 1d12h]")]
 #[checks(verify_moderator)]
 pub async fn make_user(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
     make_perm(ctx, msg, args, BotPermission::User).await
@@ -60,12 +149,51 @@ pub async fn make_user(ctx: &Context, msg: &Message, args: Args) -> CommandResul
 
 #[command]
 #[only_in(guilds)]
-#[description("Makes given role id an user on the given server")]
+#[description("Removes the permission of given user, role or channel on the given server")]
+#[usage("<user mention|role mention|role name|channel mention|everyone|id>")]
 #[checks(verify_moderator)]
 pub async fn demote(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
     make_perm(ctx, msg, args, BotPermission::None).await
 }
 
+#[command]
+#[only_in(guilds)]
+#[description("Blocks given user or role from using any command on the given server, only owners can block roles")]
+#[usage("<user mention|role mention|role name|id> [duration like 3h or 1d12h]")]
+#[checks(verify_moderator)]
+pub async fn block(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    make_perm(ctx, msg, args, BotPermission::Blocked).await
+}
+
+#[command]
+#[only_in(guilds)]
+#[description("Removes the block of given user or role on the given server")]
+#[usage("<user mention|role mention|role name|id>")]
+#[checks(verify_moderator)]
+pub async fn unblock(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild = match msg.guild(&ctx.cache) {
+        Some(guild) => guild,
+        None => return Ok(()),
+    };
+    if let Ok(entity) = parse_entity(ctx, &guild, args.rest().trim()).await {
+        let blocked = {
+            let data = ctx.data.read().await;
+            match data.get::<BotConfig>() {
+                Some(bot_config) => bot_config.read().await.get_entity_permission(guild.id, entity) == BotPermission::Blocked,
+                None => false,
+            }
+        };
+        if !blocked {
+            check_msg(msg.channel_id.send_message(&ctx.http, |m| {
+                m.content(format!("{} is not blocked!", entity.mention()));
+                m.allowed_mentions(|am| am.empty_parse())
+            }).await);
+            return Ok(());
+        }
+    }
+    make_perm(ctx, msg, args, BotPermission::None).await
+}
+
 #[command]
 #[only_in(guilds)]
 #[description("Configures if all users default to the User permission")]
@@ -91,9 +219,178 @@ pub async fn set_user_default(ctx: &Context, msg: &Message, mut args: Args) -> C
         },
     };
     if let Some(guild) = msg.guild_id{
+        let old = bot_config.get_guild_user_default(guild);
         bot_config.set_guild_user_default(guild, setting);
+        if check_write(ctx, msg, write_guild_config(&bot_config, guild)).await {
+            let entry = AuditEntry::new(guild, msg.author.id, "user_default", old.to_string(), setting.to_string());
+            audit(ctx, bot_config.get_guild_log_channel(guild), entry).await;
+        }
+    }
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[description("Shows or sets the command prefix of the given server")]
+#[usage("Without argument the current prefix is shown, `reset` restores the global default.")]
+#[checks(verify_admin)]
+pub async fn prefix(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let data = ctx.data.write().await;
+
+    let bot_config = match data.get::<BotConfig>() {
+        Some(v) => v,
+        None => {
+            msg.reply(ctx, "There was a problem getting the bot config!").await.unwrap();
+            return Ok(());
+        },
+    };
+    let mut bot_config = bot_config.write().await;
+
+    let guild = match msg.guild_id {
+        Some(guild) => guild,
+        None => return Ok(()),
+    };
+
+    let new_prefix = match args.single::<String>() {
+        Ok(value) => value,
+        Err(_) => {
+            let current = bot_config.get_guild_prefix(Some(guild));
+            check_msg(msg.channel_id.say(&ctx.http, format!("Current prefix is `{}`", current)).await);
+            return Ok(());
+        },
+    };
+
+    let old = bot_config.get_guild_setting(guild, "prefix").unwrap_or_default();
+    if new_prefix == "reset" {
+        bot_config.set_guild_prefix(guild, None);
+    } else if valid_prefix(&new_prefix) {
+        bot_config.set_guild_prefix(guild, Some(new_prefix));
+    } else {
+        check_msg(msg.channel_id.say(&ctx.http, "Prefix must be 1 to 8 characters without whitespace!").await);
+        return Ok(());
     }
-    write_config(&bot_config).expect("Config could not be written!");
+    if !check_write(ctx, msg, write_guild_config(&bot_config, guild)).await {
+        return Ok(());
+    }
+    let new = bot_config.get_guild_setting(guild, "prefix").unwrap_or_default();
+    audit(ctx, bot_config.get_guild_log_channel(guild), AuditEntry::new(guild, msg.author.id, "prefix", old, new)).await;
+
+    let current = bot_config.get_guild_prefix(Some(guild));
+    check_msg(msg.channel_id.say(&ctx.http, format!("Prefix set to `{}`", current)).await);
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[command]
+#[only_in(guilds)]
+#[description("Shows or sets the modules enabled on the given server")]
+#[usage("Modules latex/music/soundboard/all separated by spaces or commas are allowed, `default` restores the global bot mode.")]
+#[checks(verify_admin)]
+pub async fn modules(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let data = ctx.data.write().await;
+
+    let bot_config = match data.get::<BotConfig>() {
+        Some(v) => v,
+        None => {
+            msg.reply(ctx, "There was a problem getting the bot config!").await.unwrap();
+            return Ok(());
+        },
+    };
+    let mut bot_config = bot_config.write().await;
+
+    let guild = match msg.guild_id {
+        Some(guild) => guild,
+        None => return Ok(()),
+    };
+
+    let names: Vec<&str> = args.rest().split(|c: char| c == ',' || c.is_whitespace())
+                                       .filter(|name| !name.is_empty())
+                                       .collect();
+    if names.is_empty() {
+        let modes = bot_config.get_guild_modes(guild);
+        check_msg(msg.channel_id.say(&ctx.http, format!("Enabled modules: {:?}", modes)).await);
+        return Ok(());
+    }
+
+    let old = bot_config.get_guild_setting(guild, "modules").unwrap_or_default();
+    if names == ["default"] {
+        bot_config.set_guild_modes(guild, None);
+    } else {
+        let mut modes = HashSet::new();
+        for name in names {
+            match name.parse::<BotModes>() {
+                Ok(mode) => {
+                    modes.insert(mode);
+                },
+                Err(why) => {
+                    check_msg(msg.channel_id.say(&ctx.http, why).await);
+                    return Ok(());
+                },
+            }
+        }
+        bot_config.set_guild_modes(guild, Some(modes));
+    }
+    if !check_write(ctx, msg, write_guild_config(&bot_config, guild)).await {
+        return Ok(());
+    }
+    let new = bot_config.get_guild_setting(guild, "modules").unwrap_or_default();
+    audit(ctx, bot_config.get_guild_log_channel(guild), AuditEntry::new(guild, msg.author.id, "modules", old, new)).await;
+
+    let modes = bot_config.get_guild_modes(guild);
+    check_msg(msg.channel_id.say(&ctx.http, format!("Enabled modules: {:?}", modes)).await);
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[description("Shows the audit log of setting and permission changes on the given server, newest first")]
+#[usage("[page]")]
+#[checks(verify_admin)]
+pub async fn audit_log(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    const PAGE_SIZE: usize = 10;
+
+    let guild = match msg.guild_id {
+        Some(guild) => guild,
+        None => return Ok(()),
+    };
+    let page = match args.single::<usize>() {
+        Ok(page) if page > 0 => page,
+        Ok(_) => {
+            check_msg(msg.channel_id.say(&ctx.http, "Pages start at 1!").await);
+            return Ok(());
+        },
+        Err(_) => 1,
+    };
+    let entries = match read_audit_entries(guild) {
+        Ok(entries) => entries,
+        Err(why) => {
+            error!("Audit log could not be read: {:?}", why);
+            check_msg(msg.channel_id.say(&ctx.http, "Audit log could not be read!").await);
+            return Ok(());
+        },
+    };
+    if entries.is_empty() {
+        check_msg(msg.channel_id.say(&ctx.http, "The audit log is empty.").await);
+        return Ok(());
+    }
+
+    let pages = (entries.len() + PAGE_SIZE - 1) / PAGE_SIZE;
+    if page > pages {
+        check_msg(msg.channel_id.say(&ctx.http, format!("There are only {} pages!", pages)).await);
+        return Ok(());
+    }
+    let lines: Vec<String> = entries.iter()
+                                    .skip((page - 1) * PAGE_SIZE)
+                                    .take(PAGE_SIZE)
+                                    .map(|entry| entry.describe())
+                                    .collect();
+
+    check_msg(msg.channel_id.send_message(&ctx.http, |m| {
+        m.content(format!("Audit log page {}/{}:\n{}", page, pages, truncate_lines(&lines.join("\n"), 1900)));
+        m.allowed_mentions(|am| am.empty_parse())
+    }).await);
+
+    Ok(())
+}
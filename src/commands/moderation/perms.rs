@@ -0,0 +1,120 @@
+use serenity::client::Context;
+use serenity::framework::standard::{Args, CommandResult};
+use serenity::framework::standard::macros::{command, group};
+use serenity::model::channel::Message;
+use serenity::model::id::UserId;
+
+use crate::bot_utils::*;
+use crate::entity_id::entity_name;
+
+#[group]
+#[prefixes("perms")]
+#[only_in(guilds)]
+#[summary = "Inspect the permissions of this server"]
+#[default_command(list)]
+#[commands(list, explain)]
+#[checks(command_access)]
+pub struct Perms;
+
+#[command]
+#[description("Lists every permission entry of the given server")]
+#[checks(verify_moderator)]
+pub async fn list(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild = match msg.guild(&ctx.cache) {
+        Some(guild) => guild,
+        None => return Ok(()),
+    };
+
+    let (entries, temporary_grants) = {
+        let data = ctx.data.read().await;
+
+        let bot_config = match data.get::<BotConfig>() {
+            Some(v) => v,
+            None => {
+                msg.reply(ctx, "There was a problem getting the bot config!").await.unwrap();
+                return Ok(());
+            },
+        };
+        let bot_config = bot_config.read().await;
+
+        match bot_config.server_config(guild.id) {
+            Some((server, _)) => (server.entity_permissions().clone(), server.temporary_grants().clone()),
+            None => {
+                check_msg(msg.channel_id.say(&ctx.http, "Server config doesnt exist!").await);
+                return Ok(());
+            },
+        }
+    };
+
+    let mut lines = Vec::new();
+    for (entity, perm) in &entries {
+        let until = match temporary_grants.get(entity) {
+            Some(grant) => format!(" until <t:{}:f>", grant.expires),
+            None => String::new(),
+        };
+        lines.push(format!("{}: {:?}{}", entity_name(ctx, &guild, *entity).await, perm, until));
+    }
+    lines.sort();
+
+    let say_content = if lines.is_empty() {
+        "No permission entries.".to_string()
+    } else {
+        format!("Permission entries:\n{}", truncate_lines(&lines.join("\n"), 1900))
+    };
+    check_msg(msg.channel_id.send_message(&ctx.http, |m| {
+        m.content(say_content);
+        m.allowed_mentions(|am| am.empty_parse())
+    }).await);
+
+    Ok(())
+}
+
+#[command]
+#[description("Explains how the permission of a user in this channel is computed")]
+#[usage("[user], without a user your own permission is explained")]
+#[checks(verify_user)]
+pub async fn explain(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let user = if args.is_empty() {
+        msg.author.id
+    } else {
+        match args.single::<UserId>() {
+            Ok(user) => user,
+            Err(_) => {
+                check_msg(msg.channel_id.say(&ctx.http, "No valid user provided!").await);
+                return Ok(());
+            },
+        }
+    };
+    let explanation = match explain_permission(ctx, msg, user).await {
+        Ok(explanation) => explanation,
+        Err(why) => {
+            check_msg(msg.channel_id.say(&ctx.http, format!("Could not resolve the permission: {:?}", why)).await);
+            return Ok(());
+        },
+    };
+
+    let lines: Vec<String> = explanation.sources
+                                        .iter()
+                                        .enumerate()
+                                        .map(|(index, (source, perm))| {
+                                            let perm = match perm {
+                                                Some(perm) => format!("{:?}", perm),
+                                                None => "-".to_string(),
+                                            };
+                                            let winner = if explanation.winner == Some(index) { " ◀" } else { "" };
+                                            format!("{}: {}{}", source, perm, winner)
+                                        })
+                                        .collect();
+
+    check_msg(msg.channel_id.send_message(&ctx.http, |m| {
+        m.embed(|e| {
+            e.title("Permission explanation");
+            e.description(format!("<@{}> is {:?} in <#{}>", user, explanation.permission, msg.channel_id));
+            e.field("Considered", truncate_lines(&lines.join("\n"), 1024), false);
+            e.footer(|f| f.text("A block wins over every grant, otherwise the highest grant wins."));
+            e
+        })
+    }).await);
+
+    Ok(())
+}
@@ -2,16 +2,18 @@ use serenity::client::Context;
 use serenity::framework::standard::{Args, CommandResult};
 use serenity::framework::standard::macros::{command, group};
 use serenity::model::channel::{Channel, Message};
+use tracing::error;
 
 use crate::bot_utils::*;
+use crate::presence_utils;
 
 #[group]
-#[owners_only]
+#[checks(owners_only, command_access)]
 // Limit all commands to be guild-restricted.
 #[only_in(guilds)]
 // Summary only appears when listing multiple groups.
 #[summary = "Commands for server owners"]
-#[commands(slow_mode)]
+#[commands(slow_mode, presence, reload_config, orphans)]
 pub struct Owner;
 
 #[command]
@@ -21,7 +23,7 @@ pub async fn slow_mode(ctx: &Context, msg: &Message, mut args: Args) -> CommandR
         if let Err(why) =
             msg.channel_id.edit(&ctx.http, |c| c.rate_limit_per_user(slow_mode_rate_seconds)).await
         {
-            println!("Error setting channel's slow mode rate: {:?}", why);
+            error!("Error setting channel's slow mode rate: {:?}", why);
 
             format!("Failed to set slow mode to `{}` seconds.", slow_mode_rate_seconds)
         } else {
@@ -37,4 +39,106 @@ pub async fn slow_mode(ctx: &Context, msg: &Message, mut args: Args) -> CommandR
     msg.channel_id.say(&ctx.http, say_content).await?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[command]
+#[description("Sets the bot presence, multiple status texts are separated by `|`")]
+#[usage("<playing|listening|watching|competing> <text> [| <text>...] with placeholders {guilds}, {commands} and {track}")]
+#[checks(verify_owner)]
+pub async fn presence(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let (kind, texts) = args.rest().split_once(char::is_whitespace).unwrap_or((args.rest(), ""));
+    let kind = match presence_utils::parse_activity_type(kind.trim_end_matches(',')) {
+        Some(kind) => kind,
+        None => {
+            check_msg(msg.channel_id.say(&ctx.http, "Activity must be one of playing/listening/watching/competing!").await);
+            return Ok(());
+        },
+    };
+    let texts: Vec<String> = texts.split('|')
+                                  .map(|text| text.trim().to_string())
+                                  .filter(|text| !text.is_empty())
+                                  .collect();
+    if texts.is_empty() {
+        check_msg(msg.channel_id.say(&ctx.http, "No status text provided!").await);
+        return Ok(());
+    }
+
+    {
+        let data = ctx.data.write().await;
+
+        let bot_config = match data.get::<BotConfig>() {
+            Some(v) => v,
+            None => {
+                msg.reply(ctx, "There was a problem getting the bot config!").await.unwrap();
+                return Ok(());
+            },
+        };
+        let mut bot_config = bot_config.write().await;
+        bot_config.set_activity(kind, texts);
+        save_config(ctx, msg, &bot_config).await;
+    }
+
+    presence_utils::apply_presence(ctx, 0).await;
+    check_msg(msg.channel_id.say(&ctx.http, "Presence updated").await);
+
+    Ok(())
+}
+
+#[command]
+#[description("Re-reads the config file and shows what changed")]
+#[checks(verify_owner)]
+pub async fn reload_config(ctx: &Context, msg: &Message) -> CommandResult {
+    let reloaded = crate::bot_utils::reload_config(&ctx.data).await;
+    if reloaded.is_ok() {
+        crate::commands::slash::update_commands(&ctx.http, &ctx.cache, &ctx.data).await;
+    }
+    let say_content = match reloaded {
+        Ok(changes) if changes.is_empty() => "Config reloaded, nothing changed.".to_string(),
+        Ok(changes) => format!("Config reloaded:\n```\n{}\n```", truncate_lines(&changes.join("\n"), 1900)),
+        Err(why) => format!("Config reload failed: {}", why),
+    };
+
+    msg.channel_id.say(&ctx.http, say_content).await?;
+
+    Ok(())
+}
+
+#[command]
+#[description("Lists configs of guilds the bot is no longer part of")]
+#[usage("`prune` removes all of them")]
+#[checks(verify_owner)]
+pub async fn orphans(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let prune = args.single::<String>().map(|arg| arg == "prune").unwrap_or(false);
+    let current_guilds = ctx.cache.guilds();
+
+    let say_content = {
+        let data = ctx.data.write().await;
+
+        let bot_config = match data.get::<BotConfig>() {
+            Some(v) => v,
+            None => {
+                msg.reply(ctx, "There was a problem getting the bot config!").await.unwrap();
+                return Ok(());
+            },
+        };
+        let mut bot_config = bot_config.write().await;
+
+        let orphans = bot_config.orphaned_servers(&current_guilds);
+        if orphans.is_empty() {
+            "No orphaned guild configs.".to_string()
+        } else if prune {
+            for guild in &orphans {
+                bot_config.prune_server(*guild);
+            }
+            save_config(ctx, msg, &bot_config).await;
+            format!("Pruned {} orphaned guild configs.", orphans.len())
+        } else {
+            let list: Vec<String> = orphans.iter().map(|guild| guild.to_string()).collect();
+            format!("Orphaned guild configs:\n```\n{}\n```", list.join("\n"))
+        }
+    };
+
+    msg.channel_id.say(&ctx.http, say_content).await?;
+
+    Ok(())
+}
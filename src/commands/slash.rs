@@ -0,0 +1,482 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::Instant;
+
+use serenity::builder::{CreateApplicationCommand, CreateApplicationCommands, CreateEmbed};
+use serenity::cache::Cache;
+use serenity::client::Context;
+use serenity::http::Http;
+use serenity::framework::standard::Reason;
+use serenity::model::application::command::{Command, CommandOptionType};
+use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
+use serenity::model::application::interaction::autocomplete::AutocompleteInteraction;
+use serenity::model::application::interaction::{Interaction, InteractionResponseType};
+use serenity::model::channel::{AttachmentType, Channel};
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use serenity::prelude::{RwLock, TypeMap};
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::bot_utils::*;
+use crate::commands::audio::{change_volume, join_voice, leave_voice};
+use crate::commands::audio::music::{play_url, stop_track};
+use crate::commands::audio::soundboard::play_sound;
+use crate::commands::find_command;
+use crate::commands::general::permission_embed;
+use crate::commands::latex::{math_source, render_latex};
+use crate::commands::moderation::change_permission;
+use crate::entity_id::parse_entity;
+use crate::metrics_utils::metrics;
+use crate::shutdown_utils::shutting_down;
+use crate::stats_utils::BotStats;
+
+/// Discord shows at most this many autocomplete choices.
+const MAX_CHOICES: usize = 25;
+
+/// What a slash command responds with.
+enum SlashReply {
+    Text(String),
+    Embed(CreateEmbed),
+    Image(Vec<u8>),
+}
+
+impl From<&str> for SlashReply {
+    fn from(text: &str) -> Self {
+        SlashReply::Text(text.to_string())
+    }
+}
+
+impl From<String> for SlashReply {
+    fn from(text: String) -> Self {
+        SlashReply::Text(text)
+    }
+}
+
+fn text_option<'a>(command: &'a mut CreateApplicationCommand, name: &str, description: &str, required: bool, autocomplete: bool) -> &'a mut CreateApplicationCommand {
+    command.create_option(|option| {
+        option.name(name)
+              .description(description)
+              .kind(CommandOptionType::String)
+              .required(required)
+              .set_autocomplete(autocomplete)
+    })
+}
+
+fn permission_command<'a>(command: &'a mut CreateApplicationCommand, name: &str, description: &str, temporary: bool) -> &'a mut CreateApplicationCommand {
+    command.name(name).description(description).dm_permission(false);
+    text_option(command, "entity", "User, role, channel or everyone", true, true);
+    if temporary {
+        text_option(command, "duration", "Duration of the grant like 3h or 1d12h, permanent without", false, false);
+    }
+    command
+}
+
+/// Declares the slash commands, each is named after the prefix command it runs.
+fn create_commands(commands: &mut CreateApplicationCommands) -> &mut CreateApplicationCommands {
+    commands
+        .create_application_command(|command| {
+            command.name("math").description("Renders a LaTeX formula");
+            text_option(command, "formula", "The formula without $", true, false)
+        })
+        .create_application_command(|command| {
+            command.name("tex").description("Renders a LaTeX document");
+            text_option(command, "source", "The LaTeX source", true, false)
+        })
+        .create_application_command(|command| {
+            command.name("join").description("Joins your voice channel").dm_permission(false)
+        })
+        .create_application_command(|command| {
+            command.name("leave").description("Leaves the voice channel").dm_permission(false)
+        })
+        .create_application_command(|command| {
+            command.name("play").description("Plays a video or audio URL").dm_permission(false);
+            text_option(command, "url", "URL of a video or audio", true, false)
+        })
+        .create_application_command(|command| {
+            command.name("stop").description("Stops the current song").dm_permission(false)
+        })
+        .create_application_command(|command| {
+            command.name("sb").description("Plays a sound").dm_permission(false);
+            text_option(command, "path", "Path of the sound in the sound directory", true, true)
+        })
+        .create_application_command(|command| {
+            command.name("set_volume").description("Sets the volume of the bot").dm_permission(false);
+            command.create_option(|option| {
+                option.name("volume")
+                      .description("Volume from 10 to 100")
+                      .kind(CommandOptionType::Integer)
+                      .min_int_value(10)
+                      .max_int_value(100)
+                      .required(true)
+            })
+        })
+        .create_application_command(|command| permission_command(command, "make_admin", "Makes a user, role or channel an admin", true))
+        .create_application_command(|command| permission_command(command, "make_moderator", "Makes a user, role or channel a moderator", true))
+        .create_application_command(|command| permission_command(command, "make_user", "Makes a user, role or channel a user", true))
+        .create_application_command(|command| permission_command(command, "demote", "Removes the permission of a user, role or channel", false))
+        .create_application_command(|command| {
+            command.name("whois").description("Shows the permission of a user in this channel").dm_permission(false);
+            command.create_option(|option| {
+                option.name("user")
+                      .description("The user")
+                      .kind(CommandOptionType::User)
+                      .required(true)
+            })
+        })
+}
+
+/// Whether the commands are registered globally and per guild, so they are only overwritten
+/// when the configured mode changes and not on every reconnect.
+#[derive(Default)]
+struct Registrations {
+    global: Option<bool>,
+    guilds: HashMap<GuildId, bool>,
+}
+
+static REGISTRATIONS: OnceLock<Mutex<Registrations>> = OnceLock::new();
+
+fn registrations() -> MutexGuard<'static, Registrations> {
+    REGISTRATIONS.get_or_init(Default::default).lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Registers the slash commands globally if configured so, otherwise removes global ones.
+pub async fn register_global_commands(http: &Http, mode: SlashCommands) {
+    let registered = mode == SlashCommands::Global;
+    if registrations().global == Some(registered) {
+        return;
+    }
+    let result = if registered {
+        Command::set_global_application_commands(http, create_commands).await.map(|_| ())
+    } else {
+        // only clear commands left over from a run in global mode
+        match Command::get_global_application_commands(http).await {
+            Ok(commands) if commands.is_empty() => Ok(()),
+            Ok(_) => Command::set_global_application_commands(http, |commands| commands).await.map(|_| ()),
+            Err(why) => Err(why),
+        }
+    };
+    match result {
+        Ok(()) => registrations().global = Some(registered),
+        Err(why) => error!("Could not register global slash commands: {:?}", why),
+    }
+}
+
+/// Registers the slash commands in the guild if configured so, otherwise removes its ones.
+pub async fn register_guild_commands(http: &Http, guild: GuildId, mode: SlashCommands) {
+    let registered = mode == SlashCommands::Guild;
+    if registrations().guilds.get(&guild) == Some(&registered) {
+        return;
+    }
+    let result = if registered {
+        guild.set_application_commands(http, create_commands).await.map(|_| ())
+    } else {
+        // only clear commands left over from a run in guild mode
+        match guild.get_application_commands(http).await {
+            Ok(commands) if commands.is_empty() => Ok(()),
+            Ok(_) => guild.set_application_commands(http, |commands| commands).await.map(|_| ()),
+            Err(why) => Err(why),
+        }
+    };
+    match result {
+        Ok(()) => {
+            registrations().guilds.insert(guild, registered);
+        },
+        Err(why) => error!("Could not register slash commands in guild {}: {:?}", guild, why),
+    }
+}
+
+/// Brings the registered commands in line with the configured mode, e.g. after a reload.
+pub async fn update_commands(http: &Http, cache: &Cache, data: &RwLock<TypeMap>) {
+    let mode = slash_commands(data).await;
+    register_global_commands(http, mode).await;
+    for guild in cache.guilds() {
+        register_guild_commands(http, guild, mode).await;
+    }
+}
+
+pub async fn slash_commands(data: &RwLock<TypeMap>) -> SlashCommands {
+    let data = data.read().await;
+    match data.get::<BotConfig>() {
+        Some(bot_config) => bot_config.read().await.get_slash_commands(),
+        None => SlashCommands::Disabled,
+    }
+}
+
+fn reason_text(reason: Reason) -> String {
+    match reason {
+        Reason::User(why) | Reason::UserAndLog { user: why, .. } => why,
+        _ => "You can't use this command!".to_string(),
+    }
+}
+
+/// Applies the checks of the prefix command of the same name, i.e. blocks, the permission and
+/// channel restrictions, and modules.
+async fn check_access(ctx: &Context, name: &str, guild: Option<GuildId>, channel: ChannelId, user: UserId) -> Result<(), String> {
+    if shutting_down() {
+        return Err("The bot is shutting down!".to_string());
+    }
+    let (group, command) = find_command(name).ok_or_else(|| format!("Unknown command `{}`!", name))?;
+
+    check_command_access(ctx, guild, channel, user, command.options).await.map_err(reason_text)?;
+    check_modules(ctx, guild, group_modules(group)).await.map_err(reason_text)?;
+    Ok(())
+}
+
+fn string_option(command: &ApplicationCommandInteraction, name: &str) -> Option<String> {
+    command.data.options.iter()
+                        .find(|option| option.name == name)
+                        .and_then(|option| option.value.as_ref())
+                        .and_then(|value| value.as_str())
+                        .map(str::to_string)
+}
+
+fn integer_option(command: &ApplicationCommandInteraction, name: &str) -> Option<i64> {
+    command.data.options.iter()
+                        .find(|option| option.name == name)
+                        .and_then(|option| option.value.as_ref())
+                        .and_then(|value| value.as_i64())
+}
+
+async fn run_permission_command(ctx: &Context, command: &ApplicationCommandInteraction, guild: GuildId, perm: BotPermission) -> Result<SlashReply, SlashReply> {
+    let guild_data = match guild.to_guild_cached(ctx) {
+        Some(guild) => guild,
+        None => return Err("Server is not available!".into()),
+    };
+    let entity = match parse_entity(ctx, &guild_data, &string_option(command, "entity").unwrap_or_default()).await {
+        Ok(entity) => entity,
+        Err(why) => return Err(why.into()),
+    };
+    let duration = match string_option(command, "duration").map(|input| parse_duration(&input)).transpose() {
+        Ok(duration) => duration,
+        Err(why) => return Err(why.into()),
+    };
+    change_permission(ctx, guild, command.channel_id, command.user.id, entity, duration, perm).await
+        .map(SlashReply::from)
+        .map_err(SlashReply::from)
+}
+
+/// Runs a slash command. Returns its reply, as error if the command failed.
+async fn execute(ctx: &Context, command: &ApplicationCommandInteraction) -> Result<SlashReply, SlashReply> {
+    let name = command.data.name.as_str();
+    match name {
+        "math" | "tex" => {
+            let source = string_option(command, "formula").or_else(|| string_option(command, "source")).unwrap_or_default();
+            let source = if name == "math" { math_source(&source) } else { source };
+            return match render_latex(source).await {
+                Some(image) => Ok(SlashReply::Image(image)),
+                None => Err("Invalid LaTeX syntax!".into()),
+            };
+        },
+        _ => {},
+    }
+
+    let guild = match command.guild_id {
+        Some(guild) => guild,
+        None => return Err("This command only works in servers!".into()),
+    };
+    match name {
+        "join" => match guild.to_guild_cached(ctx) {
+            Some(guild) => match join_voice(ctx, &guild, command.user.id).await {
+                Ok(()) => Ok("Joined voice channel".into()),
+                Err(why) => Err(why.into()),
+            },
+            None => Err("Server is not available!".into()),
+        },
+        "leave" => leave_voice(ctx, guild).await.map(SlashReply::from).map_err(SlashReply::from),
+        "play" => play_url(ctx, guild, &string_option(command, "url").unwrap_or_default()).await.map(SlashReply::from).map_err(SlashReply::from),
+        "stop" => stop_track(ctx, guild).await.map(SlashReply::from).map_err(SlashReply::from),
+        "sb" => play_sound(ctx, guild, &string_option(command, "path").unwrap_or_default()).await.map(SlashReply::from).map_err(SlashReply::from),
+        "set_volume" => {
+            let volume = integer_option(command, "volume").unwrap_or_default().clamp(0, u8::MAX as i64) as u8;
+            match change_volume(ctx, guild, command.user.id, volume).await {
+                Ok(volume) => Ok(format!("Volume set to {}", volume).into()),
+                Err(why) => Err(why.into()),
+            }
+        },
+        "make_admin" => run_permission_command(ctx, command, guild, BotPermission::Admin).await,
+        "make_moderator" => run_permission_command(ctx, command, guild, BotPermission::Moderator).await,
+        "make_user" => run_permission_command(ctx, command, guild, BotPermission::User).await,
+        "demote" => run_permission_command(ctx, command, guild, BotPermission::None).await,
+        "whois" => {
+            let user = string_option(command, "user").and_then(|user| user.parse::<u64>().ok()).map(UserId);
+            match user {
+                Some(user) => match permission_embed(ctx, Some(guild), command.channel_id, user).await {
+                    Ok(embed) => Ok(SlashReply::Embed(embed)),
+                    Err(why) => Err(why.into()),
+                },
+                None => Err("No valid user provided!".into()),
+            }
+        },
+        _ => Err(format!("Unknown command `{}`!", name).into()),
+    }
+}
+
+async fn run_command(ctx: &Context, command: &ApplicationCommandInteraction) {
+    info!("Got slash command '{}' by user '{}'", command.data.name, command.user.name);
+    if let Err(why) = check_access(ctx, &command.data.name, command.guild_id, command.channel_id, command.user.id).await {
+        let result = command.create_interaction_response(&ctx.http, |response| {
+            response.kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|data| data.content(why).ephemeral(true))
+        }).await;
+        if let Err(why) = result {
+            warn!("Error responding to interaction: {:?}", why);
+        }
+        return;
+    }
+
+    // Rendering and sourcing audio can take longer than Discord waits for a response.
+    if let Err(why) = command.create_interaction_response(&ctx.http, |response| {
+        response.kind(InteractionResponseType::DeferredChannelMessageWithSource)
+    }).await {
+        warn!("Error responding to interaction: {:?}", why);
+        return;
+    }
+
+    metrics().commands.with_label_values(&[command.data.name.as_str()]).inc();
+    let start = Instant::now();
+    let (reply, success) = match execute(ctx, command).await {
+        Ok(reply) => (reply, true),
+        Err(reply) => (reply, false),
+    };
+    if !success {
+        metrics().command_failures.with_label_values(&[command.data.name.as_str()]).inc();
+    }
+    if let Some(stats) = ctx.data.read().await.get::<BotStats>() {
+        stats.write().await.record(command.guild_id, &command.data.name, success, start.elapsed());
+    }
+    let result = command.create_followup_message(&ctx.http, |followup| {
+        match reply {
+            SlashReply::Text(text) => followup.content(text),
+            SlashReply::Embed(embed) => followup.add_embed(embed),
+            SlashReply::Image(image) => followup.add_file(AttachmentType::Bytes {
+                data: Cow::from(image),
+                filename: "image.png".to_string(),
+            }),
+        };
+        followup.allowed_mentions(|am| am.empty_parse())
+    }).await;
+    if let Err(why) = result {
+        warn!("Error responding to interaction: {:?}", why);
+    }
+}
+
+/// Suggests the files and directories of the sound directory next to the typed path.
+fn path_choices(sound_dir: &Path, input: &str) -> Vec<(String, String)> {
+    let (dir, prefix) = match input.rfind('/') {
+        Some(index) => (&input[..=index], &input[index + 1..]),
+        None => ("", input),
+    };
+    if !inside_dir(Path::new(dir)) {
+        return Vec::new();
+    }
+    let entries = match std::fs::read_dir(sound_dir.join(dir)) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut choices: Vec<String> = entries.filter_map(Result::ok)
+                                          .filter_map(|entry| {
+                                              let name = entry.file_name().into_string().ok()?;
+                                              if name.starts_with('.') || !name.starts_with(prefix) {
+                                                  return None;
+                                              }
+                                              let suffix = if entry.path().is_dir() { "/" } else { "" };
+                                              Some(format!("{}{}{}", dir, name, suffix))
+                                          })
+                                          .filter(|path| path.len() <= 100)
+                                          .collect();
+    choices.sort();
+    choices.into_iter().take(MAX_CHOICES).map(|path| (path.clone(), path)).collect()
+}
+
+/// Suggests everyone and the roles, channels and members of the guild matching the input.
+fn entity_choices(ctx: &Context, guild: Option<GuildId>, input: &str) -> Vec<(String, String)> {
+    let guild = match guild.and_then(|guild| guild.to_guild_cached(ctx)) {
+        Some(guild) => guild,
+        None => return Vec::new(),
+    };
+    let input = input.to_lowercase();
+    let matches = |name: &str| name.to_lowercase().contains(&input);
+
+    let mut choices = Vec::new();
+    if matches("everyone") {
+        choices.push(("@everyone".to_string(), "everyone".to_string()));
+    }
+    let mut roles: Vec<(String, String)> = guild.roles.values()
+                                                .filter(|role| role.id.0 != guild.id.0 && matches(&role.name))
+                                                .map(|role| (format!("@{}", role.name), format!("<@&{}>", role.id)))
+                                                .collect();
+    roles.sort();
+    let mut channels: Vec<(String, String)> = guild.channels.iter()
+                                                   .filter_map(|(id, channel)| match channel {
+                                                       Channel::Guild(channel) if matches(&channel.name) => Some((format!("#{}", channel.name), format!("<#{}>", id))),
+                                                       _ => None,
+                                                   })
+                                                   .collect();
+    channels.sort();
+    let mut members: Vec<(String, String)> = guild.members.values()
+                                                  .filter(|member| matches(&member.display_name()) || matches(&member.user.name))
+                                                  .map(|member| (member.display_name().into_owned(), format!("<@{}>", member.user.id)))
+                                                  .collect();
+    members.sort();
+
+    choices.extend(roles);
+    choices.extend(channels);
+    choices.extend(members);
+    choices.into_iter()
+           .map(|(name, value)| (name.chars().take(100).collect(), value))
+           .take(MAX_CHOICES)
+           .collect()
+}
+
+async fn complete(ctx: &Context, autocomplete: &AutocompleteInteraction) {
+    let focused = match autocomplete.data.options.iter().find(|option| option.focused) {
+        Some(option) => option,
+        None => return,
+    };
+    let input = focused.value.as_ref().and_then(|value| value.as_str()).unwrap_or_default();
+
+    // Suggestions reveal guild members and files, so they are limited to users of the command.
+    let choices = match check_access(ctx, &autocomplete.data.name, autocomplete.guild_id, autocomplete.channel_id, autocomplete.user.id).await {
+        Ok(()) => match (autocomplete.data.name.as_str(), focused.name.as_str()) {
+            ("sb", "path") => {
+                let sound_dir = match ctx.data.read().await.get::<BotConfig>() {
+                    Some(bot_config) => bot_config.read().await.get_sound_dir().to_path_buf(),
+                    None => PathBuf::new(),
+                };
+                path_choices(&sound_dir, input)
+            },
+            (_, "entity") => entity_choices(ctx, autocomplete.guild_id, input),
+            _ => Vec::new(),
+        },
+        Err(_) => Vec::new(),
+    };
+
+    let result = autocomplete.create_autocomplete_response(&ctx.http, |response| {
+        for (name, value) in choices {
+            response.add_string_choice(name, value);
+        }
+        response
+    }).await;
+    if let Err(why) = result {
+        warn!("Error responding to autocomplete: {:?}", why);
+    }
+}
+
+/// Runs slash commands with the checks and logic of their prefix commands and answers
+/// autocomplete requests for their options.
+pub async fn handle_interaction(ctx: &Context, interaction: Interaction) {
+    match interaction {
+        Interaction::ApplicationCommand(command) => {
+            let span = info_span!("interaction",
+                                  guild = ?command.guild_id.map(|guild| guild.0),
+                                  channel = command.channel_id.0,
+                                  user = command.user.id.0,
+                                  command = %command.data.name);
+            run_command(ctx, &command).instrument(span).await
+        },
+        Interaction::Autocomplete(autocomplete) => complete(ctx, &autocomplete).await,
+        _ => {},
+    }
+}
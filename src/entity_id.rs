@@ -1,64 +1,160 @@
-use serde::{Deserialize, Serialize};
-use serenity::model::id::{RoleId, UserId};
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(
-    Copy, Clone, Default, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Deserialize, Serialize,
-)]
-pub struct EntityId(pub u64);
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serenity::client::Context;
+use serenity::model::channel::Channel;
+use serenity::model::guild::Guild;
+use serenity::model::id::{ChannelId, RoleId, UserId};
+use serenity::utils::{parse_channel, parse_role, parse_username};
 
-impl PartialEq<UserId> for EntityId {
-    fn eq(&self, other: &UserId) -> bool {
-        self.0 == other.0
+/// Something a permission can be assigned to, stored as `user:<id>`, `role:<id>`,
+/// `channel:<id>` or `everyone`.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
+pub enum EntityId {
+    User(UserId),
+    Role(RoleId),
+    Channel(ChannelId),
+    Everyone,
+    /// An untyped id from a config written before entities were typed, it is resolved by
+    /// `ConfigStruct::resolve_legacy_entities` once its guild is available.
+    Legacy(u64),
+}
+
+impl EntityId {
+    /// Types a bare id by looking it up in the guild, ids that are neither the guild, a role nor
+    /// a channel are taken as users.
+    pub fn classify(guild: &Guild, id: u64) -> EntityId {
+        if id == guild.id.0 {
+            EntityId::Everyone
+        } else if guild.roles.contains_key(&RoleId(id)) {
+            EntityId::Role(RoleId(id))
+        } else if guild.channels.contains_key(&ChannelId(id)) {
+            EntityId::Channel(ChannelId(id))
+        } else {
+            EntityId::User(UserId(id))
+        }
+    }
+
+    /// Formats the entity as a Discord mention.
+    pub fn mention(&self) -> String {
+        match self {
+            EntityId::User(user) => format!("<@{}>", user),
+            EntityId::Role(role) => format!("<@&{}>", role),
+            EntityId::Channel(channel) => format!("<#{}>", channel),
+            EntityId::Everyone => "@everyone".to_string(),
+            EntityId::Legacy(id) => id.to_string(),
+        }
     }
 }
-impl PartialEq<EntityId> for UserId {
-    fn eq(&self, other: &EntityId) -> bool {
-        self.0 == other.0
+
+impl fmt::Display for EntityId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EntityId::User(user) => write!(f, "user:{}", user),
+            EntityId::Role(role) => write!(f, "role:{}", role),
+            EntityId::Channel(channel) => write!(f, "channel:{}", channel),
+            EntityId::Everyone => write!(f, "everyone"),
+            EntityId::Legacy(id) => write!(f, "legacy:{}", id),
+        }
     }
 }
 
-impl PartialEq<RoleId> for EntityId {
-    fn eq(&self, other: &RoleId) -> bool {
-        self.0 == other.0
+impl FromStr for EntityId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "everyone" {
+            return Ok(EntityId::Everyone);
+        }
+        let (kind, id) = s.split_once(':').ok_or_else(|| format!("Invalid entity `{}`!", s))?;
+        let id = id.parse::<u64>().map_err(|_| format!("Invalid entity id `{}`!", s))?;
+        match kind {
+            "user" => Ok(EntityId::User(UserId(id))),
+            "role" => Ok(EntityId::Role(RoleId(id))),
+            "channel" => Ok(EntityId::Channel(ChannelId(id))),
+            "legacy" => Ok(EntityId::Legacy(id)),
+            _ => Err(format!("Unknown entity kind `{}`!", kind)),
+        }
     }
 }
 
-impl PartialEq<EntityId> for RoleId {
-    fn eq(&self, other: &EntityId) -> bool {
-        self.0 == other.0
+impl Serialize for EntityId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
     }
 }
 
-impl From<EntityId> for UserId {
-    fn from(item: EntityId) -> Self {
-        UserId { 0: item.0 }
+impl<'de> Deserialize<'de> for EntityId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
     }
 }
+
 impl From<UserId> for EntityId {
     fn from(item: UserId) -> Self {
-        EntityId { 0: item.0 }
+        EntityId::User(item)
     }
 }
 
-impl From<EntityId> for RoleId {
-    fn from(item: EntityId) -> Self {
-        RoleId { 0: item.0 }
-    }
-}
 impl From<RoleId> for EntityId {
     fn from(item: RoleId) -> Self {
-        EntityId { 0: item.0 }
+        EntityId::Role(item)
     }
 }
 
-impl From<EntityId> for u64 {
-    fn from(item: EntityId) -> Self {
-        item.0
+impl From<ChannelId> for EntityId {
+    fn from(item: ChannelId) -> Self {
+        EntityId::Channel(item)
     }
 }
 
-impl From<u64> for EntityId {
-    fn from(item: u64) -> Self {
-        EntityId { 0: item }
+/// Parses a user or role mention, a channel mention, `everyone`, a role name or a raw id into an
+/// entity of the guild. Fails if the guild has no such user, role or channel.
+pub async fn parse_entity(ctx: &Context, guild: &Guild, input: &str) -> Result<EntityId, String> {
+    let input = input.trim();
+    let entity = if input == "everyone" || input == "@everyone" {
+        EntityId::Everyone
+    } else if let Some(id) = parse_role(input) {
+        EntityId::Role(RoleId(id))
+    } else if let Some(id) = parse_channel(input) {
+        EntityId::Channel(ChannelId(id))
+    } else if let Some(id) = parse_username(input) {
+        EntityId::User(UserId(id))
+    } else if let Ok(id) = input.parse::<u64>() {
+        EntityId::classify(guild, id)
+    } else if let Some(role) = guild.roles.values().find(|role| role.name.eq_ignore_ascii_case(input)) {
+        EntityId::Role(role.id)
+    } else {
+        return Err(format!("Unknown user, role or channel `{}`!", input));
+    };
+
+    let exists = match entity {
+        EntityId::User(user) => guild.member(ctx, user).await.is_ok(),
+        EntityId::Role(role) => guild.roles.contains_key(&role),
+        EntityId::Channel(channel) => guild.channels.contains_key(&channel),
+        EntityId::Everyone | EntityId::Legacy(_) => true,
+    };
+    if !exists {
+        return Err(format!("No {} in this server!", entity.mention()));
     }
-}
\ No newline at end of file
+    Ok(entity)
+}
+
+/// Resolves an entity to the name of the user, role or channel in the guild, falling back to its
+/// mention if it is unknown.
+pub async fn entity_name(ctx: &Context, guild: &Guild, entity: EntityId) -> String {
+    let name = match entity {
+        EntityId::User(user) => match guild.members.get(&user) {
+            Some(member) => Some(member.display_name().into_owned()),
+            None => user.to_user(ctx).await.ok().map(|user| user.tag()),
+        },
+        EntityId::Role(role) => guild.roles.get(&role).map(|role| format!("@{}", role.name)),
+        EntityId::Channel(channel) => match guild.channels.get(&channel) {
+            Some(Channel::Guild(channel)) => Some(format!("#{}", channel.name)),
+            _ => None,
+        },
+        EntityId::Everyone | EntityId::Legacy(_) => None,
+    };
+    name.unwrap_or_else(|| entity.mention())
+}
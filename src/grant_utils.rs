@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use serenity::client::Context;
+use serenity::model::id::{ChannelId, GuildId};
+use tracing::error;
+
+use crate::bot_utils::{check_msg, unix_time, write_guild_config, BotConfig};
+
+/// Revokes expired temporary grants, persists the affected guild configs and posts a note to the
+/// log channel of each guild.
+async fn revoke_expired_grants(ctx: &Context) {
+    let notes: Vec<(ChannelId, String)> = {
+        let data = ctx.data.read().await;
+        let bot_config = match data.get::<BotConfig>() {
+            Some(v) => v,
+            None => return,
+        };
+        let mut bot_config = bot_config.write().await;
+
+        let expired = bot_config.expire_grants(unix_time());
+        let mut guilds: Vec<GuildId> = expired.iter().map(|grant| grant.guild).collect();
+        guilds.sort();
+        guilds.dedup();
+        for guild in guilds {
+            if let Err(why) = write_guild_config(&bot_config, guild) {
+                error!("Config could not be written: {:?}", why);
+            }
+        }
+
+        expired.iter()
+               .filter_map(|grant| {
+                   let channel = bot_config.get_guild_log_channel(grant.guild)?;
+                   let restored = match grant.restored {
+                       Some(perm) => format!("restored {:?}", perm),
+                       None => "removed".to_string(),
+                   };
+                   Some((channel, format!("Temporary {:?} grant of {} expired, {}", grant.revoked?, grant.entity.mention(), restored)))
+               })
+               .collect()
+    };
+
+    for (channel, note) in notes {
+        check_msg(channel.send_message(&ctx.http, |m| {
+            m.content(note);
+            m.allowed_mentions(|am| am.empty_parse())
+        }).await);
+    }
+}
+
+/// Checks for expired temporary grants for as long as the bot runs.
+pub fn start_grant_expiry(ctx: Context) {
+    tokio::spawn(async move {
+        loop {
+            revoke_expired_grants(&ctx).await;
+            tokio::time::sleep(Duration::from_secs(30)).await;
+        }
+    });
+}
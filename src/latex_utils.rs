@@ -2,6 +2,7 @@ use std::sync::Once;
 use tectonic;
 
 use magick_rust::{magick_wand_genesis, MagickError, MagickWand};
+use tracing::{debug, warn};
 
 pub fn pdf_latex(input_string: &str) -> Result<Vec<u8>, tectonic::Error> {
     let template_start = r#"\documentclass[preview]{standalone}
@@ -47,7 +48,7 @@ pub fn pdf_latex(input_string: &str) -> Result<Vec<u8>, tectonic::Error> {
             sb.create(&mut status).expect("Failed to initialize the LaTeX processing session!");
 
         if let Err(w) = sess.run(&mut status) {
-            eprintln!("The LaTeX engine failed!");
+            warn!("The LaTeX engine failed!");
             return Err(w);
         }
         sess.into_file_data()
@@ -57,7 +58,7 @@ pub fn pdf_latex(input_string: &str) -> Result<Vec<u8>, tectonic::Error> {
         None => vec![],
     };
 
-    println!("Output PDF size is {} bytes", pdf_bytes.len());
+    debug!("Output PDF size is {} bytes", pdf_bytes.len());
     Ok(pdf_bytes)
 }
 
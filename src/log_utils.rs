@@ -0,0 +1,67 @@
+use std::path::Path;
+
+use serenity::async_trait;
+use serenity::client::Context;
+use serenity::framework::Framework;
+use serenity::model::channel::Message;
+use tracing::field::Empty;
+use tracing::{info_span, Instrument, Subscriber};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+fn fmt_layer<S, W>(writer: W, json: bool, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer).with_ansi(ansi);
+    if json {
+        layer.json().boxed()
+    } else {
+        layer.boxed()
+    }
+}
+
+/// Logs to stdout and, if a directory is given, to a log file in it that is rotated daily.
+/// `level` takes `RUST_LOG` style directives, e.g. `info` or `warn,discord_bot=debug`.
+///
+/// The returned guard flushes the log file when dropped, it has to live as long as the bot.
+pub fn init_logging(level: &str, json: bool, dir: Option<&Path>) -> Result<Option<WorkerGuard>, String> {
+    let filter = EnvFilter::try_new(level).map_err(|why| format!("Invalid log level `{}`: {}", level, why))?;
+
+    let (file_layer, guard) = match dir {
+        Some(dir) => {
+            let (writer, guard) = tracing_appender::non_blocking(tracing_appender::rolling::daily(dir, "discord_bot.log"));
+            (Some(fmt_layer(writer, json, false)), Some(guard))
+        },
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(fmt_layer(std::io::stdout, json, !json))
+        .with(file_layer)
+        .with(filter)
+        .try_init()
+        .map_err(|why| format!("Logging could not be set up: {}", why))?;
+    Ok(guard)
+}
+
+/// Runs every message through the wrapped framework inside a span carrying its guild, channel
+/// and author. The `before` hook records the command name once it is known.
+pub struct TracedFramework<F>(pub F);
+
+#[async_trait]
+impl<F: Framework + Send + Sync> Framework for TracedFramework<F> {
+    async fn dispatch(&self, ctx: Context, msg: Message) {
+        let span = info_span!("dispatch",
+                              guild = ?msg.guild_id.map(|guild| guild.0),
+                              channel = msg.channel_id.0,
+                              user = msg.author.id.0,
+                              command = Empty);
+        self.0.dispatch(ctx, msg).instrument(span).await
+    }
+}
@@ -1,128 +1,376 @@
 use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use serenity::async_trait;
 use serenity::framework::standard::buckets::LimitedFor;
-use serenity::framework::standard::{Args, CommandGroup, CommandResult, DispatchError, help_commands, HelpOptions};
+use serenity::framework::standard::{Args, CommandGroup, CommandResult, DispatchError, help_commands, HelpOptions, Reason};
 use serenity::framework::StandardFramework;
 use serenity::http::Http;
 use serenity::model::channel::{Message};
+use serenity::model::application::interaction::Interaction;
+use serenity::model::event::ResumedEvent;
 use serenity::model::gateway::Ready;
+use serenity::model::guild::{Guild, UnavailableGuild};
 use serenity::prelude::*;
 use serenity::framework::standard::macros::{hook, help};
 use serenity::model::id::UserId;
 use songbird::SerenityInit;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{debug, error, info, warn};
 
+mod audit_utils;
 mod bot_utils;
+mod cli_utils;
+mod grant_utils;
 mod latex_utils;
+mod log_utils;
+mod metrics_utils;
+mod presence_utils;
+mod shutdown_utils;
+mod stats_utils;
 mod commands;
 mod entity_id;
+mod storage;
 
 use commands::audio::Player;
-use crate::bot_utils::BotConfig;
+use crate::bot_utils::{BotConfig, BotPaths, BotPermission, ConfigStruct, Credentials};
 use crate::commands::general::ShardManagerContainer;
+use crate::metrics_utils::{dispatch_error_kind, metrics};
+use crate::stats_utils::BotStats;
 
-struct CommandCounter;
-impl TypeMapKey for CommandCounter {
-    type Value = HashMap<String, u64>;
+struct Handler {
+    presence_rotation_running: AtomicBool,
+    grant_expiry_running: AtomicBool,
+    stats_flush_running: AtomicBool,
+    metrics_running: AtomicBool,
 }
-
-struct Handler;
 #[async_trait]
 impl EventHandler for Handler {
-    async fn ready(&self, _: Context, ready: Ready) {
-        println!("{} is connected!", ready.user.name);
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        info!("{} is connected!", ready.user.name);
+
+        presence_utils::apply_presence(&ctx, 0).await;
+        if !self.grant_expiry_running.swap(true, Ordering::Relaxed) {
+            grant_utils::start_grant_expiry(ctx.clone());
+        }
+        if !self.stats_flush_running.swap(true, Ordering::Relaxed) {
+            stats_utils::start_stats_flush(ctx.clone());
+        }
+        let metrics_address = match ctx.data.read().await.get::<BotConfig>() {
+            Some(bot_config) => bot_config.read().await.get_metrics_address(),
+            None => None,
+        };
+        if let Some(address) = metrics_address {
+            if !self.metrics_running.swap(true, Ordering::Relaxed) {
+                metrics_utils::start_metrics_listener(ctx.clone(), address);
+            }
+        }
+        if !self.presence_rotation_running.swap(true, Ordering::Relaxed) {
+            presence_utils::start_presence_rotation(ctx.clone());
+        }
+        let mode = commands::slash::slash_commands(&ctx.data).await;
+        commands::slash::register_global_commands(&ctx.http, mode).await;
+    }
+
+    async fn resume(&self, ctx: Context, _: ResumedEvent) {
+        presence_utils::apply_presence(&ctx, 0).await;
+    }
+
+    async fn guild_create(&self, ctx: Context, guild: Guild, _is_new: bool) {
+        let data = ctx.data.read().await;
+        if let Some(bot_config) = data.get::<BotConfig>() {
+            let mut bot_config = bot_config.write().await;
+            let created = bot_config.init_server(guild.id);
+            if created {
+                info!("Created config for guild '{}'", guild.name);
+            }
+            let resolved = bot_config.resolve_legacy_entities(&guild);
+            if resolved {
+                info!("Resolved legacy permission entries of guild '{}'", guild.name);
+            }
+            if created || resolved {
+                if let Err(why) = bot_utils::write_guild_config(&bot_config, guild.id) {
+                    error!("Config could not be written: {:?}", why);
+                }
+            }
+        }
+        drop(data);
+
+        let mode = commands::slash::slash_commands(&ctx.data).await;
+        commands::slash::register_guild_commands(&ctx.http, guild.id, mode).await;
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        commands::slash::handle_interaction(&ctx, interaction).await;
+    }
+
+    async fn guild_delete(&self, ctx: Context, incomplete: UnavailableGuild, _full: Option<Guild>) {
+        // An unavailable guild is an outage, the bot is still a member.
+        if incomplete.unavailable {
+            return;
+        }
+
+        let data = ctx.data.read().await;
+        if let Some(bot_config) = data.get::<BotConfig>() {
+            let mut bot_config = bot_config.write().await;
+            if bot_config.remove_server(incomplete.id) {
+                info!("Removed config for guild {}", incomplete.id);
+                if let Err(why) = bot_utils::write_guild_config(&bot_config, incomplete.id) {
+                    error!("Config could not be written: {:?}", why);
+                }
+            }
+        }
+    }
+}
+
+/// Validates credentials and config, printing every problem found. Returns the exit code.
+fn check_config() -> i32 {
+    let mut code = 0;
+    match bot_utils::read_credentials() {
+        Ok(_) => println!("Credentials OK"),
+        Err(why) => {
+            eprintln!("Credentials invalid: {}", why);
+            code = 1;
+        },
+    }
+    let config_path = &bot_utils::bot_paths().config;
+    match bot_utils::read_config().map_err(|why| why.to_string()).and_then(|cfg| cfg.validate()) {
+        Ok(()) => println!("Config {:?} OK", config_path),
+        Err(why) => {
+            eprintln!("Config {:?} invalid: {}", config_path, why);
+            code = 1;
+        },
+    }
+    code
+}
+
+/// Imports a YAML config file into the configured storage backend. Returns the exit code.
+fn import_config(path: &Path) -> i32 {
+    let cfg = std::fs::File::open(path)
+        .map_err(|why| why.to_string())
+        .and_then(|f| bot_utils::parse_config(f).map_err(|why| why.to_string()))
+        .and_then(|cfg| cfg.validate().map(|_| cfg));
+    match cfg {
+        Ok(cfg) => match bot_utils::write_config(&cfg) {
+            Ok(()) => {
+                println!("Imported config {:?}", path);
+                0
+            },
+            Err(why) => {
+                eprintln!("Config could not be written: {}", why);
+                1
+            },
+        },
+        Err(why) => {
+            eprintln!("Config {:?} invalid: {}", path, why);
+            1
+        },
+    }
+}
+
+fn load_credentials() -> Option<Credentials> {
+    match bot_utils::read_credentials() {
+        Ok(cred) => Some(cred),
+        Err(why) => {
+            if std::env::var("DISCORD_TOKEN").is_err() && !bot_utils::credentials_exist() {
+                bot_utils::write_example_credentials();
+            }
+            error!("Failed to read credentials: {}", why);
+            None
+        },
+    }
+}
+
+/// Reads the config, falling back to the newest valid backup if it is damaged.
+/// An example config is only written if there is no config at all.
+fn load_config() -> Option<ConfigStruct> {
+    match bot_utils::read_config().map_err(|why| why.to_string()).and_then(|cfg| cfg.validate().map(|_| cfg)) {
+        Ok(cfg) => Some(cfg),
+        Err(why) if bot_utils::config_exists() => {
+            error!("Failed to read config: {}", why);
+            match storage::storage().recover() {
+                Some((cfg, origin)) => {
+                    warn!("Recovered config from backup {}", origin);
+                    Some(cfg)
+                },
+                None => {
+                    error!("No valid config backup found!");
+                    None
+                },
+            }
+        },
+        Err(why) => {
+            error!("Failed to read config: {}", why);
+            if !bot_utils::bot_paths().config.exists() {
+                bot_utils::write_example_config();
+            }
+            None
+        },
     }
 }
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt::init();
-
-    if let Ok(cred) = bot_utils::read_credentials(){
-        if let Ok(mut cfg) = bot_utils::read_config(){
-            let http = Http::new(&cred.token);
-            // We will fetch your bot's owners and id
-            let (owners, bot_id, bot_guilds) = match http.get_current_application_info().await {
-                Ok(info) => {
-                    let mut owners = HashSet::new();
-                    if let Some(team) = info.team {
-                        owners.insert(team.owner_user_id);
-                    } else {
-                        owners.insert(info.owner.id);
-                    }
-
-                    match http.get_current_user().await {
-                        Ok(bot_id) => {
-                            let bot_guilds = bot_id.guilds(http).await
-                                                                 .unwrap_or(Vec::default());
-                            (owners, bot_id.id, bot_guilds)
-                        },
-                        Err(why) => panic!("Could not access the bot id: {:?}", why),
-                    }
-                },
-                Err(why) => panic!("Could not access application info: {:?}", why),
-            };
+    let options = match cli_utils::parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(why) => {
+            eprintln!("{}\n\n{}", why, cli_utils::USAGE);
+            std::process::exit(2);
+        },
+    };
+    if options.help {
+        println!("{}", cli_utils::USAGE);
+        return;
+    }
+    let _log_guard = match log_utils::init_logging(&options.log_level, options.log_json, options.log_dir.as_deref()) {
+        Ok(guard) => guard,
+        Err(why) => {
+            eprintln!("{}", why);
+            std::process::exit(2);
+        },
+    };
+    bot_utils::init_paths(BotPaths {
+        config: options.config_path,
+        credentials: options.credentials_path,
+        data_dir: options.data_dir,
+    });
 
-            for guild in bot_guilds{
-                cfg.init_server(guild.id);
-            }
+    match storage::open_storage(&options.storage) {
+        Ok(backend) => storage::init_storage(backend),
+        Err(why) => {
+            error!("Storage could not be opened: {}", why);
+            std::process::exit(1);
+        },
+    }
+
+    if let Some(path) = options.import_config {
+        std::process::exit(import_config(&path));
+    }
+    if options.check_config {
+        std::process::exit(check_config());
+    }
+
+    let cred = match load_credentials() {
+        Some(cred) => cred,
+        None => std::process::exit(1),
+    };
+    let mut cfg = match load_config() {
+        Some(cfg) => cfg,
+        None => std::process::exit(1),
+    };
+    let stats = stats_utils::read_stats().unwrap_or_else(|why| {
+        warn!("Stats could not be read, starting without: {:?}", why);
+        stats_utils::CommandStats::default()
+    });
+
+    let http = Http::new(&cred.token);
+    // We will fetch your bot's owners and id
+    let (bot_id, bot_guilds) = match http.get_current_application_info().await {
+        Ok(info) => {
+            cfg.set_app_owners(&info);
 
-            println!("Config {:#?}", cfg);
-            bot_utils::write_config(&cfg).expect("Config could not be written!");
-
-            let framework = StandardFramework::new()
-                .configure(|c| c
-                           .with_whitespace(true)
-                           .on_mention(Some(bot_id))
-                           .prefix("!")
-                           .delimiters(vec![", ", ","])
-                           .owners(owners))
-                .before(before) //before command execution
-                .after(after) //after command execution
-                .unrecognised_command(unknown_command)
-                .on_dispatch_error(dispatch_error)
-                .bucket("emoji", |b| b.delay(5)).await
-                .bucket("complicated", |b| b.limit(2).time_span(30).delay(5)
-                .limit_for(LimitedFor::Channel)
-                .await_ratelimits(1)
-                .delay_action(delay_action)).await
-                .help(&MY_HELP)
-                .group(&commands::general::GENERAL_GROUP)
-                .group(&commands::latex::LATEX_GROUP)
-                .group(&commands::audio::AUDIO_GROUP)
-                .group(&commands::audio::music::MUSIC_GROUP)
-                .group(&commands::moderation::MODERATION_GROUP)
-                .group(&commands::audio::soundboard::SOUNDBOARD_GROUP)
-                .group(&commands::owner::OWNER_GROUP);
-
-            let intents = GatewayIntents::non_privileged()
-                | GatewayIntents::GUILD_MESSAGES
-                | GatewayIntents::DIRECT_MESSAGES
-                | GatewayIntents::MESSAGE_CONTENT;
-
-            let mut client =
-                Client::builder(&cred.token, intents)
-                    .event_handler(Handler)
-                    .framework(framework)
-                    .register_songbird()
-                    .type_map_insert::<CommandCounter>(HashMap::default())
-                    .type_map_insert::<Player>(HashMap::default())
-                    .type_map_insert::<BotConfig>(Arc::new(RwLock::new(cfg)))
-                    .await.expect("Err creating client");
-            {
-                let mut data = client.data.write().await;
-                data.insert::<ShardManagerContainer>(Arc::clone(&client.shard_manager));
+            match http.get_current_user().await {
+                Ok(bot_id) => {
+                    let bot_guilds = bot_id.guilds(http).await
+                                                         .unwrap_or(Vec::default());
+                    (bot_id.id, bot_guilds)
+                },
+                Err(why) => panic!("Could not access the bot id: {:?}", why),
             }
+        },
+        Err(why) => panic!("Could not access application info: {:?}", why),
+    };
+
+    for guild in bot_guilds{
+        cfg.init_server(guild.id);
+    }
+
+    debug!("Config {:#?}", cfg);
+    if let Err(why) = bot_utils::write_config(&cfg) {
+        error!("Config could not be written: {:?}", why);
+    }
+
+    let mut framework = StandardFramework::new()
+        .configure(|c| c
+                   .with_whitespace(true)
+                   .on_mention(Some(bot_id))
+                   .prefix("") // prefixes are resolved per guild by `dynamic_prefix`
+                   .dynamic_prefix(dynamic_prefix)
+                   .delimiters(vec![", ", ","])
+                   // owners are checked against the config, which can change at runtime
+                   .owner_privilege(false))
+        .before(before) //before command execution
+        .after(after) //after command execution
+        .unrecognised_command(unknown_command)
+        .on_dispatch_error(dispatch_error)
+        .bucket("emoji", |b| b.delay(5)).await
+        .bucket("complicated", |b| b.limit(2).time_span(30).delay(5)
+        .limit_for(LimitedFor::Channel)
+        .await_ratelimits(1)
+        .delay_action(delay_action)).await
+        .help(&MY_HELP);
+    for group in commands::GROUPS {
+        framework = framework.group(group);
+    }
+
+    let intents = GatewayIntents::non_privileged()
+        | GatewayIntents::GUILD_MESSAGES
+        | GatewayIntents::DIRECT_MESSAGES
+        | GatewayIntents::MESSAGE_CONTENT;
+
+    let mut client =
+        Client::builder(&cred.token, intents)
+            .event_handler(Handler {
+                presence_rotation_running: AtomicBool::new(false),
+                grant_expiry_running: AtomicBool::new(false),
+                stats_flush_running: AtomicBool::new(false),
+                metrics_running: AtomicBool::new(false),
+            })
+            .framework(log_utils::TracedFramework(framework))
+            .register_songbird()
+            .type_map_insert::<BotStats>(Arc::new(RwLock::new(stats)))
+            .type_map_insert::<Player>(HashMap::default())
+            .type_map_insert::<BotConfig>(Arc::new(RwLock::new(cfg)))
+            .await.expect("Err creating client");
+    {
+        let mut data = client.data.write().await;
+        data.insert::<ShardManagerContainer>(Arc::clone(&client.shard_manager));
+    }
 
-            if let Err(why) = client.start().await {
-                println!("Client error: {:?}", why);
+    // Reload the config on SIGHUP
+    #[cfg(unix)]
+    {
+        let data = Arc::clone(&client.data);
+        let cache_and_http = Arc::clone(&client.cache_and_http);
+        tokio::spawn(async move {
+            let mut hangup = signal(SignalKind::hangup()).expect("Could not register SIGHUP handler!");
+            while hangup.recv().await.is_some() {
+                match bot_utils::reload_config(&data).await {
+                    Ok(changes) => {
+                        info!("Reloaded config, changes: {:#?}", changes);
+                        commands::slash::update_commands(&cache_and_http.http, &cache_and_http.cache, &data).await;
+                    },
+                    Err(why) => error!("Config reload failed: {}", why),
+                }
             }
-        }else{
-            bot_utils::write_example_config();
-        }
-    }else{
-        bot_utils::write_example_credentials();
+        });
+    }
+
+    // Shut down gracefully on Ctrl-C and SIGTERM
+    {
+        let data = Arc::clone(&client.data);
+        let cache = Arc::clone(&client.cache_and_http.cache);
+        tokio::spawn(async move {
+            shutdown_utils::wait_for_signal().await;
+            shutdown_utils::shutdown(data, cache).await;
+        });
+    }
+
+    if let Err(why) = client.start().await {
+        error!("Client error: {:?}", why);
     }
 }
 
@@ -133,6 +381,7 @@ async fn main() {
 #[indention_prefix = "-"]
 #[lacking_permissions = "Hide"]
 #[lacking_role = "Nothing"]
+#[lacking_conditions = "Hide"]
 #[wrong_channel = "Strike"]
 async fn my_help(
     context: &Context,
@@ -142,36 +391,71 @@ async fn my_help(
     groups: &[&'static CommandGroup],
     owners: HashSet<UserId>,
 ) -> CommandResult {
+    // the owners of the framework are never set, the config knows the current ones
+    let owners = match context.data.read().await.get::<BotConfig>() {
+        Some(bot_config) => bot_config.read().await.owners(),
+        None => owners,
+    };
     let _ = help_commands::with_embeds(context, msg, args, help_options, groups, owners).await;
     Ok(())
 }
 
+#[hook]
+async fn dynamic_prefix(ctx: &Context, msg: &Message) -> Option<String> {
+    let data = ctx.data.read().await;
+    let bot_config = data.get::<BotConfig>()?.read().await;
+    Some(bot_config.get_guild_prefix(msg.guild_id))
+}
+
 #[hook]
 async fn before(ctx: &Context, msg: &Message, command_name: &str) -> bool {
-    println!("Got command '{}' by user '{}'", command_name, msg.author.name);
+    // the dispatch span of `TracedFramework` only knows the command from here on
+    tracing::Span::current().record("command", &command_name);
+
+    if shutdown_utils::shutting_down() {
+        info!("Ignored command '{}' during shutdown", command_name);
+        return false;
+    }
 
-    // Increment the number of times this command has been run once. If
-    // the command's name does not exist in the counter, add a default
-    // value of 0.
-    let mut data = ctx.data.write().await;
-    let counter = data.get_mut::<CommandCounter>().expect("Expected CommandCounter in TypeMap.");
-    let entry = counter.entry(command_name.to_string()).or_insert(0);
-    *entry += 1;
+    // blocked users are ignored without a reply, commands turn them away in `command_access`
+    // already but the help runs no checks
+    if MY_HELP.options.names.contains(&command_name) {
+        if let Ok(BotPermission::Blocked) = bot_utils::user_permission(ctx, msg, msg.author.id).await {
+            info!("Ignored command '{}' by blocked user '{}'", command_name, msg.author.name);
+            return false;
+        }
+    }
+    info!("Got command '{}' by user '{}'", command_name, msg.author.name);
+    metrics().commands.with_label_values(&[command_name]).inc();
+
+    // Remember the start of the command, the `after` hook records it.
+    let data = ctx.data.read().await;
+    if let Some(stats) = data.get::<BotStats>() {
+        stats.write().await.start(msg.id);
+    }
 
     true // if `before` returns false, command processing doesn't happen.
 }
 
 #[hook]
-async fn after(_ctx: &Context, _msg: &Message, command_name: &str, command_result: CommandResult) {
+async fn after(ctx: &Context, msg: &Message, command_name: &str, command_result: CommandResult) {
+    let data = ctx.data.read().await;
+    if let Some(stats) = data.get::<BotStats>() {
+        stats.write().await.finish(msg.id, msg.guild_id, command_name, command_result.is_ok());
+    }
+    if command_result.is_err() {
+        metrics().command_failures.with_label_values(&[command_name]).inc();
+    }
+
     match command_result {
-        Ok(()) => println!("Processed command '{}'", command_name),
-        Err(why) => println!("Command '{}' returned error {:?}", command_name, why),
+        Ok(()) => info!("Processed command '{}'", command_name),
+        Err(why) => warn!("Command '{}' returned error {:?}", command_name, why),
     }
 }
 
 #[hook]
 async fn unknown_command(_ctx: &Context, _msg: &Message, unknown_command_name: &str) {
-    println!("Could not find command named '{}'", unknown_command_name);
+    info!("Could not find command named '{}'", unknown_command_name);
 }
 
 #[hook]
@@ -182,13 +466,22 @@ async fn delay_action(ctx: &Context, msg: &Message) {
 
 #[hook]
 async fn dispatch_error(ctx: &Context, msg: &Message, error: DispatchError, _command_name: &str) {
-    if let DispatchError::Ratelimited(info) = error {
-        // We notify them only once.
-        if info.is_first_try {
-            let _ = msg
-                .channel_id
-                .say(&ctx.http, &format!("Try this again in {} seconds.", info.as_secs()))
-                .await;
-        }
+    metrics().dispatch_errors.with_label_values(&[dispatch_error_kind(&error)]).inc();
+
+    match error {
+        DispatchError::Ratelimited(info) => {
+            // We notify them only once.
+            if info.is_first_try {
+                let _ = msg
+                    .channel_id
+                    .say(&ctx.http, &format!("Try this again in {} seconds.", info.as_secs()))
+                    .await;
+            }
+        },
+        DispatchError::CheckFailed(_, Reason::User(reason)) => {
+            let _ = msg.reply(ctx, reason).await;
+        },
+        DispatchError::CheckFailed(_, Reason::Log(reason)) => info!("{}", reason),
+        _ => {},
     }
 }
@@ -0,0 +1,181 @@
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use prometheus::{Encoder, GaugeVec, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use serenity::client::Context;
+use serenity::framework::standard::DispatchError;
+use songbird::tracks::PlayMode;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{error, info, warn};
+
+use crate::commands::audio::Player;
+use crate::commands::general::ShardManagerContainer;
+
+/// How long a connection may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Metrics of the bot, collected whether or not the listener runs.
+pub struct Metrics {
+    registry: Registry,
+    pub commands: IntCounterVec,
+    pub command_failures: IntCounterVec,
+    pub dispatch_errors: IntCounterVec,
+    pub latex_render_seconds: Histogram,
+    pub latex_failures: IntCounter,
+    voice_connections: IntGauge,
+    playing_tracks: IntGauge,
+    shard_latency_seconds: GaugeVec,
+    guilds: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("discord_bot".to_string()), None)?;
+        let metrics = Metrics {
+            commands: IntCounterVec::new(Opts::new("commands_total", "Commands processed"), &["command"])?,
+            command_failures: IntCounterVec::new(Opts::new("command_failures_total", "Commands that returned an error"), &["command"])?,
+            dispatch_errors: IntCounterVec::new(Opts::new("dispatch_errors_total", "Commands that could not be dispatched"), &["kind"])?,
+            latex_render_seconds: Histogram::with_opts(HistogramOpts::new("latex_render_seconds", "Duration of LaTeX renders")
+                                                           .buckets(vec![0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]))?,
+            latex_failures: IntCounter::new("latex_failures_total", "LaTeX renders that failed")?,
+            voice_connections: IntGauge::new("voice_connections", "Guilds the bot is connected to a voice channel in")?,
+            playing_tracks: IntGauge::new("playing_tracks", "Tracks currently playing")?,
+            shard_latency_seconds: GaugeVec::new(Opts::new("shard_latency_seconds", "Gateway heartbeat latency"), &["shard"])?,
+            guilds: IntGauge::new("guilds", "Guilds the bot is in")?,
+            registry,
+        };
+        metrics.registry.register(Box::new(metrics.commands.clone()))?;
+        metrics.registry.register(Box::new(metrics.command_failures.clone()))?;
+        metrics.registry.register(Box::new(metrics.dispatch_errors.clone()))?;
+        metrics.registry.register(Box::new(metrics.latex_render_seconds.clone()))?;
+        metrics.registry.register(Box::new(metrics.latex_failures.clone()))?;
+        metrics.registry.register(Box::new(metrics.voice_connections.clone()))?;
+        metrics.registry.register(Box::new(metrics.playing_tracks.clone()))?;
+        metrics.registry.register(Box::new(metrics.shard_latency_seconds.clone()))?;
+        metrics.registry.register(Box::new(metrics.guilds.clone()))?;
+        Ok(metrics)
+    }
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(|| Metrics::new().expect("Metrics could not be registered!"))
+}
+
+/// Names the kind of a dispatch error for the `dispatch_errors_total` metric.
+pub fn dispatch_error_kind(error: &DispatchError) -> &'static str {
+    match error {
+        DispatchError::CheckFailed(..) => "check_failed",
+        DispatchError::Ratelimited(_) => "ratelimited",
+        DispatchError::CommandDisabled => "command_disabled",
+        DispatchError::BlockedUser => "blocked_user",
+        DispatchError::BlockedGuild => "blocked_guild",
+        DispatchError::BlockedChannel => "blocked_channel",
+        DispatchError::OnlyForDM => "only_for_dm",
+        DispatchError::OnlyForGuilds => "only_for_guilds",
+        DispatchError::OnlyForOwners => "only_for_owners",
+        DispatchError::LackingRole => "lacking_role",
+        DispatchError::LackingPermissions(_) => "lacking_permissions",
+        DispatchError::NotEnoughArguments { .. } => "not_enough_arguments",
+        DispatchError::TooManyArguments { .. } => "too_many_arguments",
+        _ => "other",
+    }
+}
+
+/// Updates the gauges that are read from the bot state rather than counted.
+async fn refresh_gauges(ctx: &Context) {
+    let metrics = metrics();
+    let guilds = ctx.cache.guilds();
+    metrics.guilds.set(guilds.len() as i64);
+
+    if let Some(manager) = songbird::get(ctx).await {
+        let connections = guilds.iter().filter(|guild| manager.get(**guild).is_some()).count();
+        metrics.voice_connections.set(connections as i64);
+    }
+
+    let data = ctx.data.read().await;
+    if let Some(players) = data.get::<Player>() {
+        let mut playing = 0;
+        for track_handler in players.values() {
+            if let Ok(info) = track_handler.get_info().await {
+                if info.playing == PlayMode::Play {
+                    playing += 1;
+                }
+            }
+        }
+        metrics.playing_tracks.set(playing);
+    }
+
+    if let Some(shard_manager) = data.get::<ShardManagerContainer>() {
+        let manager = shard_manager.lock().await;
+        let runners = manager.runners.lock().await;
+        metrics.shard_latency_seconds.reset();
+        for (id, runner) in runners.iter() {
+            if let Some(latency) = runner.latency {
+                metrics.shard_latency_seconds.with_label_values(&[id.0.to_string().as_str()]).set(latency.as_secs_f64());
+            }
+        }
+    }
+}
+
+fn encode_metrics() -> String {
+    let mut buffer = Vec::new();
+    if let Err(why) = TextEncoder::new().encode(&metrics().registry.gather(), &mut buffer) {
+        error!("Metrics could not be encoded: {:?}", why);
+    }
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+/// Answers a single HTTP request, `/metrics` with the metrics and anything else with 404.
+async fn serve(ctx: &Context, mut stream: TcpStream) -> std::io::Result<()> {
+    let mut request = [0u8; 1024];
+    let read = match tokio::time::timeout(REQUEST_TIMEOUT, stream.read(&mut request)).await {
+        Ok(read) => read?,
+        Err(_) => return Err(std::io::Error::new(ErrorKind::TimedOut, "no request received")),
+    };
+    let request = String::from_utf8_lossy(&request[..read]);
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+
+    let (status, body) = if path == "/metrics" {
+        refresh_gauges(ctx).await;
+        ("200 OK", encode_metrics())
+    } else {
+        ("404 Not Found", "Not found\n".to_string())
+    };
+    let response = format!("HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                           status, body.len(), body);
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Serves the metrics for Prometheus on `http://<address>/metrics` for as long as the bot runs.
+pub fn start_metrics_listener(ctx: Context, address: SocketAddr) {
+    tokio::spawn(async move {
+        let listener = match TcpListener::bind(address).await {
+            Ok(listener) => listener,
+            Err(why) => {
+                error!("Metrics listener could not bind {}: {:?}", address, why);
+                return;
+            },
+        };
+        info!("Serving metrics on http://{}/metrics", address);
+
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let ctx = ctx.clone();
+                    tokio::spawn(async move {
+                        if let Err(why) = serve(&ctx, stream).await {
+                            warn!("Metrics request failed: {:?}", why);
+                        }
+                    });
+                },
+                Err(why) => warn!("Metrics connection failed: {:?}", why),
+            }
+        }
+    });
+}
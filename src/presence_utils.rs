@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use serenity::client::Context;
+use serenity::model::gateway::{Activity, ActivityType};
+
+use crate::bot_utils::BotConfig;
+use crate::commands::audio::Player;
+use crate::stats_utils::BotStats;
+
+pub fn parse_activity_type(input: &str) -> Option<ActivityType> {
+    match input.to_lowercase().as_str() {
+        "playing" => Some(ActivityType::Playing),
+        "listening" => Some(ActivityType::Listening),
+        "watching" => Some(ActivityType::Watching),
+        "competing" => Some(ActivityType::Competing),
+        _ => None,
+    }
+}
+
+fn create_activity(kind: ActivityType, text: String) -> Activity {
+    match kind {
+        ActivityType::Listening => Activity::listening(text),
+        ActivityType::Watching => Activity::watching(text),
+        ActivityType::Competing => Activity::competing(text),
+        _ => Activity::playing(text),
+    }
+}
+
+/// Replaces the `{guilds}`, `{commands}` and `{track}` placeholders of a status text.
+async fn fill_placeholders(ctx: &Context, text: &str) -> String {
+    let data = ctx.data.read().await;
+
+    let commands: u64 = match data.get::<BotStats>() {
+        Some(stats) => stats.read().await.total_invocations(),
+        None => 0,
+    };
+    let track = data.get::<Player>()
+                    .and_then(|players| players.values()
+                                               .find_map(|handle| handle.metadata().title.clone()))
+                    .unwrap_or_else(|| "nothing".to_string());
+
+    text.replace("{guilds}", &ctx.cache.guild_count().to_string())
+        .replace("{commands}", &commands.to_string())
+        .replace("{track}", &track)
+}
+
+/// Sets the presence to the status text at `index` of the configured rotation and returns the
+/// configured rotation interval in seconds.
+pub async fn apply_presence(ctx: &Context, index: usize) -> u64 {
+    let (kind, texts, interval) = {
+        let data = ctx.data.read().await;
+        let bot_config = match data.get::<BotConfig>() {
+            Some(v) => v,
+            None => return 60,
+        };
+        let bot_config = bot_config.read().await;
+        (bot_config.get_activity(), bot_config.get_activity_texts(), bot_config.get_activity_interval())
+    };
+
+    let text = fill_placeholders(ctx, &texts[index % texts.len()]).await;
+    ctx.set_activity(create_activity(kind, text)).await;
+    interval
+}
+
+/// Rotates through the configured status texts for as long as the bot runs.
+pub fn start_presence_rotation(ctx: Context) {
+    tokio::spawn(async move {
+        let mut index = 0usize;
+        loop {
+            let interval = apply_presence(&ctx, index).await;
+            index = index.wrapping_add(1);
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+        }
+    });
+}
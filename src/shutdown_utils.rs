@@ -0,0 +1,100 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use serenity::cache::Cache;
+use serenity::prelude::{RwLock, TypeMap};
+use songbird::serenity::SongbirdKey;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info, warn};
+
+use crate::bot_utils::{default_shutdown_timeout, write_config, BotConfig};
+use crate::commands::audio::Player;
+use crate::commands::general::ShardManagerContainer;
+use crate::stats_utils::flush_stats;
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// Whether a shutdown has started, commands are ignored from then on.
+pub fn shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::Relaxed)
+}
+
+/// Waits for Ctrl-C or SIGTERM.
+#[cfg(unix)]
+pub async fn wait_for_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Could not register SIGTERM handler!");
+    tokio::select! {
+        Ok(()) = tokio::signal::ctrl_c() => {},
+        _ = terminate.recv() => {},
+    }
+}
+
+/// Waits for Ctrl-C.
+#[cfg(not(unix))]
+pub async fn wait_for_signal() {
+    if let Err(why) = tokio::signal::ctrl_c().await {
+        error!("Could not listen for Ctrl-C: {:?}", why);
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Stops all tracks and leaves every voice channel.
+async fn leave_voice_channels(data: &RwLock<TypeMap>, cache: &Cache) {
+    let (players, manager) = {
+        let mut data = data.write().await;
+        let players = data.get_mut::<Player>().map(std::mem::take).unwrap_or_default();
+        (players, data.get::<SongbirdKey>().cloned())
+    };
+
+    for (guild, track_handler) in players {
+        if let Err(why) = track_handler.stop() {
+            warn!("Can not stop track in {}: {:?}", guild, why);
+        }
+    }
+    if let Some(manager) = manager {
+        for guild in cache.guilds() {
+            if manager.get(guild).is_some() {
+                if let Err(why) = manager.remove(guild).await {
+                    warn!("Can not leave voice channel in {}: {:?}", guild, why);
+                }
+            }
+        }
+    }
+}
+
+async fn save_state(data: &RwLock<TypeMap>) {
+    if let Some(bot_config) = data.read().await.get::<BotConfig>() {
+        if let Err(why) = write_config(&*bot_config.read().await) {
+            error!("Config could not be written: {:?}", why);
+        }
+    }
+    flush_stats(data).await;
+}
+
+/// Stops accepting commands, leaves all voice channels, saves config and stats and shuts the
+/// shards down, which lets `Client::start` return. Exits the process if this takes longer than
+/// the configured `shutdown_timeout`.
+pub async fn shutdown(data: Arc<RwLock<TypeMap>>, cache: Arc<Cache>) {
+    SHUTTING_DOWN.store(true, Ordering::Relaxed);
+    let timeout = match data.read().await.get::<BotConfig>() {
+        Some(bot_config) => bot_config.read().await.get_shutdown_timeout(),
+        None => default_shutdown_timeout(),
+    };
+    info!("Shutting down, waiting at most {}s", timeout);
+
+    let result = tokio::time::timeout(Duration::from_secs(timeout), async {
+        leave_voice_channels(&data, &cache).await;
+        save_state(&data).await;
+        let shard_manager = data.read().await.get::<ShardManagerContainer>().cloned();
+        if let Some(shard_manager) = shard_manager {
+            shard_manager.lock().await.shutdown_all().await;
+        }
+    }).await;
+
+    if result.is_err() {
+        error!("Shutdown took longer than {}s, exiting", timeout);
+        std::process::exit(1);
+    }
+}
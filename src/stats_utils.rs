@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serenity::client::Context;
+use serenity::model::id::{GuildId, MessageId};
+use serenity::prelude::{TypeMap, TypeMapKey};
+use tokio::sync::RwLock;
+use tracing::error;
+
+use crate::bot_utils::{bot_paths, unix_time};
+use crate::storage::StorageError;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Days of usage that are kept and can be queried.
+pub const MAX_STATS_DAYS: u64 = 365;
+
+/// Guild the usage of direct messages is recorded under.
+pub const DIRECT_MESSAGES: GuildId = GuildId(0);
+
+pub struct BotStats;
+impl TypeMapKey for BotStats {
+    type Value = Arc<RwLock<CommandStats>>;
+}
+
+/// Usage of a command within one day.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CommandUsage {
+    pub invocations: u64,
+    pub failures: u64,
+    /// Summed execution time in milliseconds.
+    pub total_millis: u64,
+    /// Invocations by hour of the day in UTC.
+    pub hours: [u64; 24],
+}
+
+impl CommandUsage {
+    fn merge(&mut self, other: &CommandUsage) {
+        self.invocations += other.invocations;
+        self.failures += other.failures;
+        self.total_millis += other.total_millis;
+        for (hour, count) in self.hours.iter_mut().zip(other.hours.iter()) {
+            *hour += count;
+        }
+    }
+
+    pub fn error_rate(&self) -> f64 {
+        if self.invocations == 0 {
+            0.0
+        } else {
+            self.failures as f64 / self.invocations as f64
+        }
+    }
+
+    pub fn average_millis(&self) -> u64 {
+        self.total_millis.checked_div(self.invocations).unwrap_or(0)
+    }
+}
+
+/// Usage of commands summed up over guilds and days by `CommandStats::summary`.
+#[derive(Debug, Default)]
+pub struct StatsSummary {
+    pub total: CommandUsage,
+    /// Commands sorted by their invocations, most used first.
+    pub commands: Vec<(String, CommandUsage)>,
+}
+
+impl StatsSummary {
+    /// Hours of the day in UTC sorted by their invocations, busiest first.
+    pub fn busiest_hours(&self) -> Vec<(usize, u64)> {
+        let mut hours: Vec<(usize, u64)> = self.total.hours.iter().copied().enumerate().filter(|(_, count)| *count > 0).collect();
+        hours.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hours
+    }
+}
+
+/// Command usage by guild, day since the unix epoch and command, persisted next to the config.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CommandStats {
+    guilds: HashMap<GuildId, BTreeMap<u64, HashMap<String, CommandUsage>>>,
+    /// Start of the commands currently running, by the message that invoked them.
+    #[serde(skip)]
+    running: HashMap<MessageId, Instant>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl CommandStats {
+    /// Remembers when the command of a message started to measure its duration.
+    pub fn start(&mut self, msg: MessageId) {
+        self.running.insert(msg, Instant::now());
+    }
+
+    /// Records the command of a message started with `start`.
+    pub fn finish(&mut self, msg: MessageId, guild: Option<GuildId>, command: &str, success: bool) {
+        let duration = self.running.remove(&msg).map(|start| start.elapsed()).unwrap_or_default();
+        self.record(guild, command, success, duration);
+    }
+
+    pub fn record(&mut self, guild: Option<GuildId>, command: &str, success: bool, duration: Duration) {
+        let now = unix_time();
+        let usage = self.guilds.entry(guild.unwrap_or(DIRECT_MESSAGES))
+                               .or_default()
+                               .entry(now / SECONDS_PER_DAY)
+                               .or_default()
+                               .entry(command.to_string())
+                               .or_default();
+        usage.invocations += 1;
+        if !success {
+            usage.failures += 1;
+        }
+        usage.total_millis += duration.as_millis() as u64;
+        usage.hours[((now % SECONDS_PER_DAY) / 3600) as usize] += 1;
+        self.dirty = true;
+    }
+
+    /// Drops the usage recorded before the last `days` days.
+    pub fn prune(&mut self, days: u64) {
+        let first_day = (unix_time() / SECONDS_PER_DAY).saturating_sub(days.saturating_sub(1));
+        for guild_days in self.guilds.values_mut() {
+            let kept = guild_days.split_off(&first_day);
+            if !guild_days.is_empty() {
+                self.dirty = true;
+            }
+            *guild_days = kept;
+        }
+        self.guilds.retain(|_, guild_days| !guild_days.is_empty());
+    }
+
+    /// Counts all recorded invocations.
+    pub fn total_invocations(&self) -> u64 {
+        self.guilds.values()
+                   .flat_map(|days| days.values())
+                   .flat_map(|commands| commands.values())
+                   .map(|usage| usage.invocations)
+                   .sum()
+    }
+
+    /// Sums up the usage of the last `days` days in a guild, or in all guilds if `None`.
+    pub fn summary(&self, guild: Option<GuildId>, days: u64) -> StatsSummary {
+        let first_day = (unix_time() / SECONDS_PER_DAY).saturating_sub(days.saturating_sub(1));
+        let mut commands: HashMap<String, CommandUsage> = HashMap::new();
+        let mut summary = StatsSummary::default();
+
+        let guilds = self.guilds.iter().filter(|(id, _)| guild.map_or(true, |guild| guild == **id));
+        for (_, guild_days) in guilds {
+            for (_, day) in guild_days.range(first_day..) {
+                for (command, usage) in day {
+                    commands.entry(command.clone()).or_default().merge(usage);
+                    summary.total.merge(usage);
+                }
+            }
+        }
+
+        summary.commands = commands.into_iter().collect();
+        summary.commands.sort_by(|a, b| b.1.invocations.cmp(&a.1.invocations).then_with(|| a.0.cmp(&b.0)));
+        summary
+    }
+}
+
+fn stats_path() -> PathBuf {
+    bot_paths().data_dir.join("command_stats.yml")
+}
+
+/// Reads the persisted stats, a missing file is empty.
+pub fn read_stats() -> Result<CommandStats, StorageError> {
+    match std::fs::File::open(stats_path()) {
+        Ok(f) => Ok(serde_yaml::from_reader(f)?),
+        Err(why) if why.kind() == ErrorKind::NotFound => Ok(CommandStats::default()),
+        Err(why) => Err(why.into()),
+    }
+}
+
+/// Writes the stats through a temporary file so a crash can't leave them half written.
+pub fn write_stats(stats: &CommandStats) -> Result<(), StorageError> {
+    let path = stats_path();
+    let tmp_path = path.with_extension("yml.tmp");
+    std::fs::write(&tmp_path, serde_yaml::to_string(stats)?)?;
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+/// Writes the stats of the `BotStats` entry of `data` if they changed since the last write.
+pub async fn flush_stats(data: &RwLock<TypeMap>) {
+    let data = data.read().await;
+    let stats = match data.get::<BotStats>() {
+        Some(v) => v,
+        None => return,
+    };
+    let mut stats = stats.write().await;
+
+    stats.prune(MAX_STATS_DAYS);
+    if stats.dirty {
+        match write_stats(&stats) {
+            Ok(()) => stats.dirty = false,
+            Err(why) => error!("Stats could not be written: {:?}", why),
+        }
+    }
+}
+
+/// Persists the stats every minute for as long as the bot runs.
+pub fn start_stats_flush(ctx: Context) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            flush_stats(&ctx.data).await;
+        }
+    });
+}
@@ -0,0 +1,61 @@
+use std::error;
+use std::sync::OnceLock;
+
+use serenity::model::id::GuildId;
+
+use crate::bot_utils::{bot_paths, ConfigStruct};
+use crate::entity_id::EntityId;
+
+pub mod sqlite;
+pub mod yaml;
+
+pub type StorageError = Box<dyn error::Error + Send + Sync>;
+
+/// Persistence of the bot config.
+///
+/// Backends that can't store parts of the config separately fall back to storing all of it.
+pub trait ConfigStorage: Send + Sync {
+    /// Checks if there is a stored config at all.
+    fn exists(&self) -> bool;
+
+    fn load(&self) -> Result<ConfigStruct, StorageError>;
+
+    fn save(&self, cfg: &ConfigStruct) -> Result<(), StorageError>;
+
+    /// Stores the settings and entity permissions of a single guild.
+    fn save_guild(&self, cfg: &ConfigStruct, _guild: GuildId) -> Result<(), StorageError> {
+        self.save(cfg)
+    }
+
+    /// Stores the permission of a single entity of a guild.
+    fn save_entity_permission(&self, cfg: &ConfigStruct, guild: GuildId, _entity: EntityId) -> Result<(), StorageError> {
+        self.save_guild(cfg, guild)
+    }
+
+    /// Loads the newest valid backup of the config, returning it together with its origin.
+    fn recover(&self) -> Option<(ConfigStruct, String)> {
+        None
+    }
+}
+
+static STORAGE: OnceLock<Box<dyn ConfigStorage>> = OnceLock::new();
+
+/// Opens the storage backend with the given name, `yaml` or `sqlite`.
+pub fn open_storage(kind: &str) -> Result<Box<dyn ConfigStorage>, StorageError> {
+    match kind {
+        "yaml" => Ok(Box::new(yaml::YamlStorage)),
+        "sqlite" => Ok(Box::new(sqlite::SqliteStorage::open(&bot_paths().data_dir.join("bot_config.db"))?)),
+        _ => Err(format!("Unknown storage backend `{}`!", kind).into()),
+    }
+}
+
+/// Sets the storage backend, has to be called before the config is read.
+pub fn init_storage(backend: Box<dyn ConfigStorage>) {
+    if STORAGE.set(backend).is_err() {
+        panic!("Storage already initialised!");
+    }
+}
+
+pub fn storage() -> &'static dyn ConfigStorage {
+    STORAGE.get_or_init(|| Box::new(yaml::YamlStorage) as Box<dyn ConfigStorage>).as_ref()
+}
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+use serenity::model::id::GuildId;
+
+use crate::bot_utils::{parse_config_value, BotPermission, ConfigStruct, ServerAudioStruct};
+use crate::entity_id::EntityId;
+use crate::storage::{ConfigStorage, StorageError};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS global (
+        id INTEGER PRIMARY KEY CHECK (id = 0),
+        config TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS guilds (
+        guild_id INTEGER PRIMARY KEY,
+        archived INTEGER NOT NULL,
+        settings TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS entity_permissions (
+        guild_id INTEGER NOT NULL,
+        entity TEXT NOT NULL,
+        permission TEXT NOT NULL,
+        PRIMARY KEY (guild_id, entity)
+    );";
+
+/// Stores the config in an embedded SQLite database.
+///
+/// Global and guild settings are kept as YAML documents so they go through the same migrations
+/// as the YAML config, entity permissions get a row each so a single grant is written on its own.
+pub struct SqliteStorage {
+    connection: Mutex<Connection>,
+}
+
+fn to_yaml_string(value: &impl Serialize) -> Result<String, StorageError> {
+    Ok(serde_yaml::to_string(value)?.trim_end().to_string())
+}
+
+/// Serializes `value` into a YAML mapping without the given keys.
+fn to_yaml_mapping_without(value: &impl Serialize, keys: &[&str]) -> Result<String, StorageError> {
+    let mut value = serde_yaml::to_value(value)?;
+    if let Value::Mapping(map) = &mut value {
+        for key in keys {
+            map.remove(*key);
+        }
+    }
+    to_yaml_string(&value)
+}
+
+impl SqliteStorage {
+    pub fn open(path: &Path) -> Result<Self, StorageError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(SqliteStorage {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Connection>, StorageError> {
+        self.connection.lock().map_err(|_| "Storage lock poisoned!".into())
+    }
+
+    fn write_entity(tx: &Transaction, guild: GuildId, entity: &EntityId, perm: Option<&BotPermission>) -> Result<(), StorageError> {
+        let entity = to_yaml_string(entity)?;
+        match perm {
+            Some(perm) => tx.execute("INSERT OR REPLACE INTO entity_permissions (guild_id, entity, permission) VALUES (?1, ?2, ?3)",
+                                     params![guild.0 as i64, entity, to_yaml_string(perm)?])?,
+            None => tx.execute("DELETE FROM entity_permissions WHERE guild_id = ?1 AND entity = ?2",
+                               params![guild.0 as i64, entity])?,
+        };
+        Ok(())
+    }
+
+    /// Replaces the stored settings and permissions of a guild, `None` removes the guild.
+    fn write_guild(tx: &Transaction, guild: GuildId, server: Option<(&ServerAudioStruct, bool)>) -> Result<(), StorageError> {
+        tx.execute("DELETE FROM guilds WHERE guild_id = ?1", params![guild.0 as i64])?;
+        tx.execute("DELETE FROM entity_permissions WHERE guild_id = ?1", params![guild.0 as i64])?;
+
+        if let Some((server, archived)) = server {
+            let settings = to_yaml_mapping_without(server, &["entity_permission"])?;
+            tx.execute("INSERT INTO guilds (guild_id, archived, settings) VALUES (?1, ?2, ?3)",
+                       params![guild.0 as i64, archived, settings])?;
+            for (entity, perm) in server.entity_permissions() {
+                Self::write_entity(tx, guild, entity, Some(perm))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ConfigStorage for SqliteStorage {
+    fn exists(&self) -> bool {
+        match self.lock() {
+            Ok(connection) => connection.query_row("SELECT 1 FROM global WHERE id = 0", [], |_| Ok(()))
+                                        .optional()
+                                        .map(|row| row.is_some())
+                                        .unwrap_or(false),
+            Err(_) => false,
+        }
+    }
+
+    fn load(&self) -> Result<ConfigStruct, StorageError> {
+        let connection = self.lock()?;
+
+        let global: Option<String> = connection.query_row("SELECT config FROM global WHERE id = 0", [], |row| row.get(0))
+                                               .optional()?;
+        let mut cfg: Value = match global {
+            Some(global) => serde_yaml::from_str(&global)?,
+            None => return Err("The database holds no config, import one with --import-config!".into()),
+        };
+
+        let mut permissions: HashMap<i64, Mapping> = HashMap::new();
+        {
+            let mut statement = connection.prepare("SELECT guild_id, entity, permission FROM entity_permissions")?;
+            let rows = statement.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))?;
+            for row in rows {
+                let (guild_id, entity, perm) = row?;
+                permissions.entry(guild_id)
+                           .or_default()
+                           .insert(serde_yaml::from_str(&entity)?, serde_yaml::from_str(&perm)?);
+            }
+        }
+
+        let mut servers = Mapping::new();
+        let mut archived_servers = Mapping::new();
+        {
+            let mut statement = connection.prepare("SELECT guild_id, archived, settings FROM guilds")?;
+            let rows = statement.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, bool>(1)?, row.get::<_, String>(2)?)))?;
+            for row in rows {
+                let (guild_id, archived, settings) = row?;
+                let mut settings: Value = serde_yaml::from_str(&settings)?;
+                if let Value::Mapping(map) = &mut settings {
+                    map.insert("entity_permission".into(), Value::Mapping(permissions.remove(&guild_id).unwrap_or_default()));
+                }
+                if archived {
+                    archived_servers.insert(Value::from(guild_id as u64), settings);
+                } else {
+                    servers.insert(Value::from(guild_id as u64), settings);
+                }
+            }
+        }
+
+        if let Value::Mapping(map) = &mut cfg {
+            map.insert("server_cfgs".into(), Value::Mapping(servers));
+            map.insert("archived_server_cfgs".into(), Value::Mapping(archived_servers));
+        }
+        parse_config_value(cfg)
+    }
+
+    fn save(&self, cfg: &ConfigStruct) -> Result<(), StorageError> {
+        let mut connection = self.lock()?;
+        let tx = connection.transaction()?;
+
+        let global = to_yaml_mapping_without(cfg, &["server_cfgs", "archived_server_cfgs"])?;
+        tx.execute("INSERT OR REPLACE INTO global (id, config) VALUES (0, ?1)", params![global])?;
+        tx.execute("DELETE FROM guilds", [])?;
+        tx.execute("DELETE FROM entity_permissions", [])?;
+        for (guild, server, archived) in cfg.server_configs() {
+            Self::write_guild(&tx, guild, Some((server, archived)))?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn save_guild(&self, cfg: &ConfigStruct, guild: GuildId) -> Result<(), StorageError> {
+        let mut connection = self.lock()?;
+        let tx = connection.transaction()?;
+        Self::write_guild(&tx, guild, cfg.server_config(guild))?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn save_entity_permission(&self, cfg: &ConfigStruct, guild: GuildId, entity: EntityId) -> Result<(), StorageError> {
+        let server = match cfg.server_config(guild) {
+            Some((server, _)) => server,
+            None => return Ok(()),
+        };
+
+        let mut connection = self.lock()?;
+        let tx = connection.transaction()?;
+        Self::write_entity(&tx, guild, &entity, server.entity_permissions().get(&entity))?;
+        tx.commit()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+use crate::bot_utils::{bot_paths, parse_config, ConfigStruct};
+use crate::storage::{ConfigStorage, StorageError};
+
+const BACKUP_COUNT: usize = 10;
+
+/// Stores the whole config in a single YAML file, keeping a rolling set of backups.
+pub struct YamlStorage;
+
+impl YamlStorage {
+    fn backup_dir() -> PathBuf {
+        bot_paths().data_dir.join("config_backups")
+    }
+
+    fn read_from(path: &Path) -> Result<ConfigStruct, StorageError> {
+        let f = std::fs::File::open(path)?;
+        parse_config(f)
+    }
+
+    /// Returns all config backups, newest first.
+    fn list_backups() -> Vec<PathBuf> {
+        let mut backups: Vec<(u128, PathBuf)> = match std::fs::read_dir(Self::backup_dir()) {
+            Ok(dir) => dir.filter_map(|entry| entry.ok())
+                          .filter_map(|entry| {
+                              let path = entry.path();
+                              let stamp = path.file_name()?
+                                              .to_str()?
+                                              .strip_prefix("bot_config-")?
+                                              .strip_suffix(".yml")?
+                                              .parse::<u128>().ok()?;
+                              Some((stamp, path))
+                          })
+                          .collect(),
+            Err(_) => Vec::new(),
+        };
+        backups.sort_by(|a, b| b.0.cmp(&a.0));
+        backups.into_iter().map(|(_, path)| path).collect()
+    }
+
+    /// Copies the current config into the backup directory and prunes all but the newest backups.
+    fn backup(&self) -> std::io::Result<()> {
+        if !self.exists() {
+            return Ok(());
+        }
+        std::fs::create_dir_all(Self::backup_dir())?;
+        let stamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        std::fs::copy(&bot_paths().config, Self::backup_dir().join(format!("bot_config-{}.yml", stamp)))?;
+
+        for old_backup in Self::list_backups().into_iter().skip(BACKUP_COUNT) {
+            std::fs::remove_file(old_backup)?;
+        }
+        Ok(())
+    }
+}
+
+impl ConfigStorage for YamlStorage {
+    fn exists(&self) -> bool {
+        bot_paths().config.exists()
+    }
+
+    fn load(&self) -> Result<ConfigStruct, StorageError> {
+        Self::read_from(&bot_paths().config)
+    }
+
+    /// Writes the config atomically: the new config is written and synced to a temporary file,
+    /// the previous config is backed up and the temporary file is renamed over it.
+    fn save(&self, cfg: &ConfigStruct) -> Result<(), StorageError> {
+        let config_path = &bot_paths().config;
+        let tmp_path = config_path.with_extension("yml.tmp");
+        {
+            let mut f = std::fs::File::create(&tmp_path)?;
+            serde_yaml::to_writer(&f, cfg)?;
+            f.flush()?;
+            f.sync_all()?;
+        }
+        self.backup()?;
+        std::fs::rename(&tmp_path, config_path)?;
+        Ok(())
+    }
+
+    fn recover(&self) -> Option<(ConfigStruct, String)> {
+        for path in Self::list_backups() {
+            match Self::read_from(&path) {
+                Ok(cfg) => return Some((cfg, format!("{:?}", path))),
+                Err(why) => warn!("Skipping invalid config backup {:?}: {}", path, why),
+            }
+        }
+        None
+    }
+}